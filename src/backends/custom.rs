@@ -0,0 +1,232 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+use super::{
+    execute_wallpaper_changer, find_executable, replace_resident_process, Fit, ResidentProcesses,
+    WallpaperBackend,
+};
+use crate::error::Error;
+use crate::template::{Template, TemplateError};
+
+const PLACEHOLDERS: [&str; 9] = [
+    "path",
+    "output",
+    "fit",
+    "name",
+    "stem",
+    "extension",
+    "directory",
+    "size",
+    "modified",
+];
+
+/// Runs a user supplied argv template such as `my-setter --file {path}`, filling the placeholders
+/// from the selected file.
+#[derive(Debug)]
+pub struct Custom {
+    arguments: Vec<Template>,
+    fit: Fit,
+    /// The command keeps running to draw the wallpaper, as swaybg does, and is replaced by the
+    /// next one instead of being waited for.
+    resident: bool,
+    residents: ResidentProcesses,
+}
+
+impl Custom {
+    pub fn new(template: &str, fit: Fit, resident: bool) -> Result<Self, TemplateError> {
+        let arguments = Template::parse_arguments(template, &PLACEHOLDERS)?;
+        if arguments.is_empty() {
+            return Err(TemplateError::Empty);
+        }
+        Ok(Custom {
+            arguments,
+            fit,
+            resident,
+            residents: ResidentProcesses::default(),
+        })
+    }
+}
+
+impl WallpaperBackend for Custom {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    #[tracing::instrument]
+    fn apply(&self, output: Option<&str>, selected_file: &Path) -> Result<(), Error> {
+        let arguments = self
+            .arguments
+            .iter()
+            .map(|argument| render_argument(argument, output, &self.fit, selected_file))
+            .collect::<Vec<_>>();
+        let Some((program, arguments)) = arguments.split_first() else {
+            return Err(Error::BackendFailed {
+                program: self.name().to_string(),
+                message: "the command template is empty".to_string(),
+            });
+        };
+
+        let mut command = Command::new(program);
+        command.args(arguments);
+        if self.resident {
+            replace_resident_process(self.name(), output, &mut command, &self.residents)
+        } else {
+            execute_wallpaper_changer(&mut command)
+        }
+    }
+
+    /// Only templates using `{output}` can tell outputs apart.
+    fn supports_outputs(&self) -> bool {
+        self.arguments
+            .iter()
+            .any(|argument| argument.uses("output"))
+    }
+
+    fn check(&self) -> Result<String, String> {
+        match self.arguments.first().and_then(Template::literal) {
+            Some(program) => find_executable(&program),
+            None => Ok("program chosen by a placeholder".to_string()),
+        }
+    }
+}
+
+#[tracing::instrument]
+fn render_argument(
+    argument: &Template,
+    output: Option<&str>,
+    fit: &Fit,
+    selected_file: &Path,
+) -> String {
+    argument.render(|name| match name {
+        "output" => output.unwrap_or_default().to_string(),
+        "fit" => fit.to_string(),
+        _ => get_placeholder_value(name, selected_file),
+    })
+}
+
+#[tracing::instrument]
+fn get_placeholder_value(name: &str, selected_file: &Path) -> String {
+    let lossy = |value: Option<&std::ffi::OsStr>| {
+        value
+            .map(|value| value.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+
+    match name {
+        "path" => selected_file.to_string_lossy().to_string(),
+        "name" => lossy(selected_file.file_name()),
+        "stem" => lossy(selected_file.file_stem()),
+        "extension" => lossy(selected_file.extension()),
+        "directory" => lossy(selected_file.parent().map(Path::as_os_str)),
+        "size" => fs::metadata(selected_file)
+            .map(|metadata| metadata.len().to_string())
+            .unwrap_or_default(),
+        "modified" => fs::metadata(selected_file)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs().to_string())
+            .unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, output: Option<&str>) -> Vec<String> {
+        Template::parse_arguments(template, &PLACEHOLDERS)
+            .unwrap()
+            .iter()
+            .map(|argument| {
+                render_argument(
+                    argument,
+                    output,
+                    &Fit::default(),
+                    Path::new("/wallpapers/forest.jpg"),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        assert_eq!(
+            render(
+                "setter --output {output} --mode {fit} {directory}/{stem}.{extension}",
+                Some("DP-1")
+            ),
+            [
+                "setter",
+                "--output",
+                "DP-1",
+                "--mode",
+                "fill",
+                "/wallpapers/forest.jpg"
+            ]
+        );
+        assert_eq!(
+            render("setter {name}  {path}", None),
+            ["setter", "forest.jpg", "/wallpapers/forest.jpg"]
+        );
+        assert_eq!(
+            render("setter --output={output}", None),
+            ["setter", "--output="]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        assert!(matches!(
+            Template::parse_arguments("setter {file}", &PLACEHOLDERS),
+            Err(TemplateError::UnknownPlaceholder(name, _)) if name == "file"
+        ));
+        assert!(matches!(
+            Template::parse_arguments("setter {}", &PLACEHOLDERS),
+            Err(TemplateError::UnknownPlaceholder(name, _)) if name.is_empty()
+        ));
+    }
+
+    #[test]
+    fn checks_braces() {
+        assert_eq!(render("echo {{path}} }}{{", None), ["echo", "{path}", "}{"]);
+        assert!(matches!(
+            Template::parse_arguments("setter {path", &PLACEHOLDERS),
+            Err(TemplateError::UnclosedPlaceholder(7))
+        ));
+        assert!(matches!(
+            Template::parse_arguments("setter path}", &PLACEHOLDERS),
+            Err(TemplateError::UnmatchedBrace(11))
+        ));
+    }
+
+    #[test]
+    fn groups_quoted_words() {
+        assert_eq!(
+            render(r#"sh -c 'setter "{path}"' "two words" ''"#, None),
+            [
+                "sh",
+                "-c",
+                "setter \"/wallpapers/forest.jpg\"",
+                "two words",
+                ""
+            ]
+        );
+        assert_eq!(render("a'b c'd", None), ["ab cd"]);
+        assert!(matches!(
+            Template::parse_arguments("setter 'path", &PLACEHOLDERS),
+            Err(TemplateError::UnclosedQuote('\''))
+        ));
+        assert!(matches!(
+            Template::parse_arguments("  ", &PLACEHOLDERS),
+            Ok(arguments) if arguments.is_empty()
+        ));
+        assert!(matches!(
+            Custom::new(" ", Fit::default(), false),
+            Err(TemplateError::Empty)
+        ));
+    }
+}
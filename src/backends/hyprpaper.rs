@@ -0,0 +1,97 @@
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tracing::info;
+
+use super::WallpaperBackend;
+use crate::error::Error;
+use crate::images::ImageFormat;
+
+const SUPPORTED_FORMATS: [ImageFormat; 3] =
+    [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::Webp];
+
+const SOCKET_NAME: &str = ".hyprpaper.sock";
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Talks to a running hyprpaper through its IPC socket.
+#[derive(Debug, Default)]
+pub struct Hyprpaper;
+
+impl Hyprpaper {
+    pub fn new() -> Self {
+        Hyprpaper
+    }
+
+    /// hyprpaper keeps its socket next to Hyprland's, which moved from `/tmp/hypr` to
+    /// `$XDG_RUNTIME_DIR/hypr` in newer releases.
+    #[tracing::instrument]
+    fn get_socket_path(&self) -> Option<PathBuf> {
+        let signature = env::var("HYPRLAND_INSTANCE_SIGNATURE").ok()?;
+        dirs::runtime_dir()
+            .into_iter()
+            .chain([PathBuf::from("/tmp")])
+            .map(|dir| dir.join("hypr").join(&signature).join(SOCKET_NAME))
+            .find(|path| path.exists())
+    }
+
+    fn failure(&self, message: String) -> Error {
+        Error::BackendFailed {
+            program: self.name().to_string(),
+            message,
+        }
+    }
+
+    #[tracing::instrument]
+    fn request(&self, socket_path: &Path, message: &str) -> Result<(), Error> {
+        let mut reply = String::new();
+        let result = UnixStream::connect(socket_path).and_then(|mut stream| {
+            stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+            stream.write_all(message.as_bytes())?;
+            stream.read_to_string(&mut reply)
+        });
+
+        match result {
+            Ok(_) if reply.trim() == "ok" => Ok(()),
+            Ok(_) => Err(self.failure(format!("rejected '{}': {}", message, reply.trim()))),
+            Err(err) => Err(self.failure(format!("failed to send '{}': {}", message, err))),
+        }
+    }
+}
+
+impl WallpaperBackend for Hyprpaper {
+    fn name(&self) -> &'static str {
+        "hyprpaper"
+    }
+
+    #[tracing::instrument]
+    fn apply(&self, output: Option<&str>, selected_file: &Path) -> Result<(), Error> {
+        let socket_path = self
+            .get_socket_path()
+            .ok_or_else(|| self.failure("socket not found, is hyprpaper running?".to_string()))?;
+
+        let selected_file = selected_file.to_string_lossy();
+        self.request(&socket_path, &format!("preload {}", selected_file))?;
+        self.request(
+            &socket_path,
+            &format!("wallpaper {},{}", output.unwrap_or_default(), selected_file),
+        )?;
+
+        if let Err(err) = self.request(&socket_path, "unload unused") {
+            info!("Previous wallpapers stay preloaded in hyprpaper: {}", err);
+        }
+        Ok(())
+    }
+
+    fn supported_formats(&self) -> &'static [ImageFormat] {
+        &SUPPORTED_FORMATS
+    }
+
+    fn check(&self) -> Result<String, String> {
+        self.get_socket_path()
+            .map(|socket_path| socket_path.display().to_string())
+            .ok_or_else(|| "hyprpaper socket not found, is hyprpaper running?".to_string())
+    }
+}
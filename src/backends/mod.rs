@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Debug};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use tracing::{info, warn};
+
+use crate::error::Error;
+use crate::images::{ImageFormat, ALL_FORMATS};
+use crate::outputs::{discover_outputs, Output};
+
+pub use custom::Custom;
+pub use hyprpaper::Hyprpaper;
+pub use swaybg::Swaybg;
+pub use swww::{Swww, SwwwDaemon};
+pub use transition::{Transition, TransitionType};
+pub use wbg::Wbg;
+
+mod custom;
+mod hyprpaper;
+mod swaybg;
+mod swww;
+mod transition;
+mod wbg;
+
+pub const BACKEND_NAMES: [&str; 5] = ["swww", "swaybg", "hyprpaper", "wbg", "custom"];
+
+const RESIDENT_STARTUP_GRACE: Duration = Duration::from_millis(500);
+
+const FITS: [&str; 5] = ["fill", "fit", "stretch", "center", "tile"];
+
+/// Longest process name the kernel keeps in `/proc/<pid>/comm`.
+const COMM_MAX_LEN: usize = 15;
+
+/// How a wallpaper is scaled to its output, named as swaybg's modes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fit(String);
+
+impl Default for Fit {
+    fn default() -> Self {
+        Fit("fill".to_string())
+    }
+}
+
+impl FromStr for Fit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_lowercase();
+        if FITS.contains(&value.as_str()) {
+            Ok(Fit(value))
+        } else {
+            Err(format!(
+                "Unknown fit {}, expected one of {}.",
+                value,
+                FITS.join(", ")
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+pub trait WallpaperBackend: Debug {
+    /// Name the backend is selected by.
+    fn name(&self) -> &'static str;
+
+    /// Sets `selected_file` as the wallpaper of `output`, or of every output when `None`.
+    fn apply(&self, output: Option<&str>, selected_file: &Path) -> Result<(), Error>;
+
+    /// Like `apply`, cross-fading from the current wallpaper over `duration` when the backend
+    /// can animate the change.
+    fn fade(
+        &self,
+        output: Option<&str>,
+        selected_file: &Path,
+        _duration: Duration,
+    ) -> Result<(), Error> {
+        self.apply(output, selected_file)
+    }
+
+    /// Lists the outputs wallpapers can be set on individually.
+    fn outputs(&self) -> Vec<Output> {
+        discover_outputs()
+    }
+
+    /// Image formats the backend is able to display.
+    fn supported_formats(&self) -> &'static [ImageFormat] {
+        &ALL_FORMATS
+    }
+
+    /// Whether `apply` can target a single output.
+    fn supports_outputs(&self) -> bool {
+        true
+    }
+
+    /// Checks that what the backend runs or talks to is there, describing what was found.
+    fn check(&self) -> Result<String, String>;
+}
+
+/// Builds the backend called `name`, running `command` instead of its default executable when
+/// given. For the custom backend `command` is the argv template, which is validated here so a bad
+/// template is reported before anything is spawned. `transition` and `swww_daemon` are only used
+/// by swww, `fit` by swaybg and the custom backend and `custom_resident` by the latter.
+#[tracing::instrument]
+pub fn from_name(
+    name: &str,
+    command: Option<String>,
+    transition: Transition,
+    swww_daemon: SwwwDaemon,
+    fit: Fit,
+    custom_resident: bool,
+) -> Result<Box<dyn WallpaperBackend>, String> {
+    let backend: Box<dyn WallpaperBackend> = match name {
+        "swww" => Box::new(Swww::new(command, transition, swww_daemon)),
+        "swaybg" => Box::new(Swaybg::new(command, fit)),
+        "hyprpaper" => Box::new(Hyprpaper::new()),
+        "wbg" => Box::new(Wbg::new(command)),
+        "custom" => Box::new(
+            Custom::new(command.as_deref().unwrap_or_default(), fit, custom_resident)
+                .map_err(|err| format!("Invalid custom command template: {}.", err))?,
+        ),
+        _ => {
+            return Err(format!(
+                "Unknown wallpaper backend {}, expected one of {}.",
+                name,
+                BACKEND_NAMES.join(", ")
+            ))
+        }
+    };
+    Ok(backend)
+}
+
+/// Looks `command` up in `PATH` unless it is already a path.
+#[tracing::instrument]
+fn find_executable(command: &str) -> Result<String, String> {
+    let candidates = if command.contains('/') {
+        vec![PathBuf::from(command)]
+    } else {
+        env::var_os("PATH")
+            .map(|path| {
+                env::split_paths(&path)
+                    .map(|dir| dir.join(command))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    candidates
+        .into_iter()
+        .find(|candidate| candidate.is_file())
+        .map(|executable| executable.display().to_string())
+        .ok_or_else(|| format!("{} not found in PATH", command))
+}
+
+fn get_program(command: &Command) -> String {
+    command.get_program().to_string_lossy().to_string()
+}
+
+/// Runs `command` to completion, failing with what it wrote to stderr when it exits with an error.
+#[tracing::instrument]
+fn execute_wallpaper_changer(command: &mut Command) -> Result<(), Error> {
+    let output = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| Error::BackendSpawn {
+            program: get_program(command),
+            err,
+        })?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(Error::BackendFailed {
+        program: get_program(command),
+        message: if stderr.is_empty() {
+            format!("exited with {}", output.status)
+        } else {
+            format!("exited with {}: {}", output.status, stderr)
+        },
+    })
+}
+
+/// Wallpaper clients started by this process that keep running, by output, so each one is waited
+/// for once replaced instead of being left as a zombie.
+#[derive(Debug, Default)]
+struct ResidentProcesses {
+    children: Mutex<HashMap<Option<String>, Child>>,
+}
+
+/// Starts a wallpaper client that keeps running to draw the wallpaper, then stops the instance
+/// started for the same output before, by this process or a previous run, so the old wallpaper
+/// stays visible until the new one is up.
+#[tracing::instrument]
+fn replace_resident_process(
+    name: &str,
+    output: Option<&str>,
+    command: &mut Command,
+    residents: &ResidentProcesses,
+) -> Result<(), Error> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|err| Error::BackendSpawn {
+            program: get_program(command),
+            err,
+        })?;
+
+    thread::sleep(RESIDENT_STARTUP_GRACE);
+    if let Ok(Some(status)) = child.try_wait() {
+        return Err(Error::BackendFailed {
+            program: get_program(command),
+            message: format!("exited with {}", status),
+        });
+    }
+
+    let pid_file_path = get_pid_file_path(name, output);
+    let previous_pid = get_previous_pid(&pid_file_path, &get_program(command));
+    if let Err(err) = fs::write(&pid_file_path, child.id().to_string()) {
+        warn!("Failed to write {}: {}", pid_file_path.display(), err);
+    }
+
+    let previous_child = residents
+        .children
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(output.map(str::to_string), child);
+    match previous_child {
+        Some(mut previous_child) => {
+            let previous_pid = Pid::from_raw(previous_child.id() as i32);
+            stop_resident_process(name, previous_pid);
+            if let Err(err) = previous_child.wait() {
+                warn!(
+                    "Failed to wait for {} instance {}: {}",
+                    name, previous_pid, err
+                );
+            }
+        }
+        None => {
+            if let Some(previous_pid) = previous_pid {
+                stop_resident_process(name, previous_pid);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn stop_resident_process(name: &str, pid: Pid) {
+    info!("Stopping previous {} instance {}", name, pid);
+    if let Err(err) = kill(pid, Signal::SIGTERM) {
+        warn!("Failed to stop {} instance {}: {}", name, pid, err);
+    }
+}
+
+#[tracing::instrument]
+fn get_pid_file_path(name: &str, output: Option<&str>) -> PathBuf {
+    let file_name = match output {
+        Some(output) => format!("random-wallpaper-{}-{}.pid", name, output),
+        None => format!("random-wallpaper-{}.pid", name),
+    };
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(file_name)
+}
+
+/// Reads the pid recorded in `pid_file_path`, ignoring it if that process is no longer running
+/// `program` so a recycled pid is never signalled.
+#[tracing::instrument]
+fn get_previous_pid(pid_file_path: &Path, program: &str) -> Option<Pid> {
+    let pid = fs::read_to_string(pid_file_path)
+        .ok()?
+        .trim()
+        .parse::<i32>()
+        .ok()?;
+    let process_name = fs::read(format!("/proc/{}/comm", pid)).ok()?;
+    let program_name = Path::new(program).file_name()?.as_bytes();
+    let program_name = &program_name[..program_name.len().min(COMM_MAX_LEN)];
+    if process_name.strip_suffix(b"\n").unwrap_or(&process_name) == program_name {
+        Some(Pid::from_raw(pid))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,61 @@
+use std::path::Path;
+use std::process::Command;
+
+use super::{find_executable, replace_resident_process, Fit, ResidentProcesses, WallpaperBackend};
+use crate::error::Error;
+use crate::images::ImageFormat;
+
+/// Formats with a gdk-pixbuf loader in a typical install.
+const SUPPORTED_FORMATS: [ImageFormat; 6] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::Gif,
+    ImageFormat::Bmp,
+    ImageFormat::Webp,
+    ImageFormat::Tiff,
+];
+
+#[derive(Debug)]
+pub struct Swaybg {
+    command: String,
+    fit: Fit,
+    residents: ResidentProcesses,
+}
+
+impl Swaybg {
+    pub fn new(command: Option<String>, fit: Fit) -> Self {
+        Swaybg {
+            command: command.unwrap_or_else(|| "swaybg".to_string()),
+            fit,
+            residents: ResidentProcesses::default(),
+        }
+    }
+}
+
+impl WallpaperBackend for Swaybg {
+    fn name(&self) -> &'static str {
+        "swaybg"
+    }
+
+    #[tracing::instrument]
+    fn apply(&self, output: Option<&str>, selected_file: &Path) -> Result<(), Error> {
+        replace_resident_process(
+            self.name(),
+            output,
+            Command::new(&self.command)
+                .args(["--output", output.unwrap_or("*")])
+                .arg("--image")
+                .arg(selected_file)
+                .args(["--mode", &self.fit.to_string()]),
+            &self.residents,
+        )
+    }
+
+    fn supported_formats(&self) -> &'static [ImageFormat] {
+        &SUPPORTED_FORMATS
+    }
+
+    fn check(&self) -> Result<String, String> {
+        find_executable(&self.command)
+    }
+}
@@ -0,0 +1,346 @@
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use super::{
+    execute_wallpaper_changer, find_executable, get_program, Transition, TransitionType,
+    WallpaperBackend,
+};
+use crate::config;
+use crate::error::Error;
+use crate::images::ImageFormat;
+use crate::outputs::{discover_outputs, parse_swww_query, run_query, Output};
+
+const SUPPORTED_FORMATS: [ImageFormat; 7] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::Gif,
+    ImageFormat::Bmp,
+    ImageFormat::Webp,
+    ImageFormat::Tiff,
+    ImageFormat::Qoi,
+];
+
+/// Changes are tried again this many times after finding swww-daemon not running.
+const MAX_ATTEMPTS: u32 = 3;
+
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What to do when swww-daemon isn't running, as happens right after logging in.
+#[derive(Debug, Clone)]
+pub struct SwwwDaemon {
+    /// Start swww-daemon instead of only waiting for something else to.
+    pub start: bool,
+    /// How long to wait for it to answer before giving up.
+    pub timeout: Duration,
+}
+
+impl Default for SwwwDaemon {
+    fn default() -> Self {
+        SwwwDaemon {
+            start: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Swww {
+    command: String,
+    transition: Transition,
+    daemon: SwwwDaemon,
+    /// The swww-daemon started by this process, waited for once it exits.
+    daemon_child: Mutex<Option<Child>>,
+}
+
+impl Swww {
+    pub fn new(command: Option<String>, transition: Transition, daemon: SwwwDaemon) -> Self {
+        Swww {
+            command: command.unwrap_or_else(|| "swww".to_string()),
+            transition,
+            daemon,
+            daemon_child: Mutex::new(None),
+        }
+    }
+
+    fn change(
+        &self,
+        output: Option<&str>,
+        selected_file: &Path,
+        transition: &Transition,
+    ) -> Result<(), Error> {
+        let mut command = Command::new(&self.command);
+        command.arg("img");
+        if let Some(output) = output {
+            command.args(["--outputs", output]);
+        }
+        let fps = transition.fps.or_else(|| detect_fps(output));
+        execute_wallpaper_changer(
+            command
+                .args(transition.args(transition.pick_type(), fps))
+                .arg(selected_file),
+        )
+    }
+
+    /// Retries the change when swww-daemon isn't running yet, after starting it if configured to
+    /// and waiting for it to become ready.
+    fn apply_transition(
+        &self,
+        output: Option<&str>,
+        selected_file: &Path,
+        transition: &Transition,
+    ) -> Result<(), Error> {
+        let mut started = false;
+        let mut attempt = 1;
+        loop {
+            info!(
+                "Changing the wallpaper with {}, attempt {}/{}",
+                self.command, attempt, MAX_ATTEMPTS
+            );
+            let message = match self.change(output, selected_file, transition) {
+                Err(Error::BackendFailed { message, .. }) if is_daemon_not_running(&message) => {
+                    message
+                }
+                result => return result,
+            };
+            warn!("swww-daemon is not running: {}", message);
+            if attempt == MAX_ATTEMPTS {
+                return Err(Error::BackendNotRunning {
+                    program: "swww-daemon".to_string(),
+                    message,
+                });
+            }
+            if self.daemon.start && !started {
+                self.start_daemon()?;
+                started = true;
+            }
+            if !self.wait_until_ready() {
+                return Err(Error::BackendNotRunning {
+                    program: "swww-daemon".to_string(),
+                    message: format!(
+                        "not ready after {}",
+                        config::format_duration(self.daemon.timeout)
+                    ),
+                });
+            }
+            attempt += 1;
+        }
+    }
+
+    /// swww-daemon next to the swww executable, or the one in `PATH`.
+    fn daemon_command(&self) -> PathBuf {
+        match Path::new(&self.command).parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory.join("swww-daemon"),
+            _ => PathBuf::from("swww-daemon"),
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        Command::new(&self.command)
+            .arg("query")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    /// Starts swww-daemon in its own process group, so it outlives this process and the signals
+    /// sent to its terminal.
+    #[tracing::instrument(skip(self))]
+    fn start_daemon(&self) -> Result<(), Error> {
+        if let Some(status) = self.reap_daemon() {
+            info!("The swww-daemon started before exited with {}", status);
+        }
+        let mut command = Command::new(self.daemon_command());
+        info!("Starting {}", get_program(&command));
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .spawn()
+            .map_err(|err| Error::BackendSpawn {
+                program: get_program(&command),
+                err,
+            })?;
+        *self
+            .daemon_child
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(child);
+        Ok(())
+    }
+
+    /// Waits for the swww-daemon started by this process if it exited, returning its status.
+    fn reap_daemon(&self) -> Option<ExitStatus> {
+        let mut daemon_child = self
+            .daemon_child
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let status = daemon_child.as_mut()?.try_wait().ok()??;
+        *daemon_child = None;
+        Some(status)
+    }
+
+    /// Waits up to the timeout for swww-daemon to answer queries.
+    #[tracing::instrument(skip(self))]
+    fn wait_until_ready(&self) -> bool {
+        let deadline = Instant::now() + self.daemon.timeout;
+        loop {
+            if self.is_ready() {
+                return true;
+            }
+            if let Some(status) = self.reap_daemon() {
+                warn!("swww-daemon exited with {}", status);
+                return false;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Frame rate matching the refresh rate of `output`, or of the fastest output when changing all
+/// of them.
+#[tracing::instrument]
+fn detect_fps(output: Option<&str>) -> Option<u32> {
+    let fps = discover_outputs()
+        .into_iter()
+        .filter(|candidate| match output {
+            Some(output) => candidate.name == output,
+            None => true,
+        })
+        .filter_map(|candidate| candidate.refresh_rate)
+        .max_by(f64::total_cmp)
+        .map(|refresh_rate| refresh_rate.round() as u32)
+        .filter(|fps| *fps > 0);
+    match fps {
+        Some(fps) => info!("Using the refresh rate of {} fps for the transition", fps),
+        None => warn!("No refresh rate found, leaving the transition fps to swww"),
+    }
+    fps
+}
+
+/// Whether swww failed because it could not reach swww-daemon, judging by its error message.
+fn is_daemon_not_running(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("socket")
+        && [
+            "connect",
+            "not found",
+            "no such file",
+            "swww-daemon",
+            "refused",
+        ]
+        .iter()
+        .any(|hint| message.contains(hint))
+}
+
+impl WallpaperBackend for Swww {
+    fn name(&self) -> &'static str {
+        "swww"
+    }
+
+    #[tracing::instrument]
+    fn apply(&self, output: Option<&str>, selected_file: &Path) -> Result<(), Error> {
+        self.apply_transition(output, selected_file, &self.transition)
+    }
+
+    /// Uses a `fade` transition lasting `duration`, keeping the other transition options.
+    #[tracing::instrument]
+    fn fade(
+        &self,
+        output: Option<&str>,
+        selected_file: &Path,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        let transition = Transition {
+            types: vec![TransitionType::fade()],
+            duration: duration.as_secs_f64(),
+            ..self.transition.clone()
+        };
+        self.apply_transition(output, selected_file, &transition)
+    }
+
+    #[tracing::instrument]
+    fn outputs(&self) -> Vec<Output> {
+        run_query(Command::new(&self.command).arg("query"))
+            .map(|stdout| parse_swww_query(&stdout))
+            .filter(|outputs| !outputs.is_empty())
+            .unwrap_or_else(discover_outputs)
+    }
+
+    fn supported_formats(&self) -> &'static [ImageFormat] {
+        &SUPPORTED_FORMATS
+    }
+
+    fn check(&self) -> Result<String, String> {
+        let executable = find_executable(&self.command)?;
+        if !self.is_ready() {
+            return Err(format!(
+                "{} found, but swww-daemon is not running",
+                executable
+            ));
+        }
+        Ok(executable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_a_missing_daemon() {
+        for message in [
+            "Error: Socket file '/run/user/1000/wayland-1-swww-daemon.socket' not found. Make \
+             sure swww-daemon is running, and that the --namespace argument matches the one \
+             used to start the daemon",
+            "Error: \"Socket file not found. Are you sure swww-daemon is running?\"",
+            "Error: failed to connect to socket at \"/run/user/1000/swww.socket\": Connection \
+             refused (os error 111)",
+            "Error: \"Failed to connect to socket: No such file or directory (os error 2)\"",
+        ] {
+            assert!(is_daemon_not_running(message), "{}", message);
+        }
+
+        for message in [
+            "Error: failed to load image \"/a.png\": No such file or directory (os error 2)",
+            "Error: \"none of the requested outputs are valid\"",
+            "error: unexpected argument '--transition-bezier' found",
+            "",
+        ] {
+            assert!(!is_daemon_not_running(message), "{}", message);
+        }
+    }
+
+    #[test]
+    fn reaps_the_started_daemon() {
+        let swww = Swww::new(
+            Some("/nonexistent/swww".to_string()),
+            Transition::default(),
+            SwwwDaemon {
+                start: true,
+                timeout: Duration::from_secs(5),
+            },
+        );
+        assert_eq!(swww.daemon_command(), Path::new("/nonexistent/swww-daemon"));
+        assert!(matches!(
+            swww.start_daemon(),
+            Err(Error::BackendSpawn { .. })
+        ));
+
+        *swww.daemon_child.lock().unwrap() = Some(Command::new("true").spawn().unwrap());
+        let started = Instant::now();
+        assert!(!swww.wait_until_ready());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(swww.daemon_child.lock().unwrap().is_none());
+    }
+}
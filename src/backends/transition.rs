@@ -0,0 +1,215 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand_core::OsRng;
+use rand_distr::{Distribution, Uniform};
+
+const TRANSITION_TYPES: [&str; 14] = [
+    "none", "simple", "fade", "left", "right", "top", "bottom", "wipe", "wave", "grow", "center",
+    "any", "outer", "random",
+];
+
+const POSITIONS: [&str; 9] = [
+    "center",
+    "top",
+    "left",
+    "right",
+    "bottom",
+    "top-left",
+    "top-right",
+    "bottom-left",
+    "bottom-right",
+];
+
+/// How swww animates from one wallpaper to the next. Options left unset are not passed, so swww
+/// uses its own defaults.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    /// Types to pick from, a different random one for each change when there are several.
+    pub types: Vec<TransitionType>,
+    pub step: u32,
+    /// Seconds.
+    pub duration: f64,
+    /// Detected from the refresh rate of the outputs when unset.
+    pub fps: Option<u32>,
+    pub pos: Option<Position>,
+    /// Degrees, used by `wipe` and `wave`.
+    pub angle: Option<f64>,
+    pub bezier: Option<Bezier>,
+    pub wave: Option<Wave>,
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Transition {
+            types: vec![TransitionType("any".to_string())],
+            step: 30,
+            duration: 3.0,
+            fps: None,
+            pos: None,
+            angle: None,
+            bezier: None,
+            wave: None,
+        }
+    }
+}
+
+impl Transition {
+    /// Picks one of the types for the next change.
+    pub fn pick_type(&self) -> Option<&TransitionType> {
+        match self.types.len() {
+            0 => None,
+            1 => self.types.first(),
+            len => self.types.get(Uniform::new(0, len).sample(&mut OsRng)),
+        }
+    }
+
+    /// `swww img` arguments for a change using `transition_type` at `fps` frames per second.
+    pub fn args(&self, transition_type: Option<&TransitionType>, fps: Option<u32>) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut arg = |name: &str, value: String| {
+            args.push(format!("--transition-{}", name));
+            args.push(value);
+        };
+        if let Some(transition_type) = transition_type {
+            arg("type", transition_type.to_string());
+        }
+        arg("step", self.step.to_string());
+        arg("duration", self.duration.to_string());
+        if let Some(fps) = fps {
+            arg("fps", fps.to_string());
+        }
+        if let Some(pos) = &self.pos {
+            arg("pos", pos.to_string());
+        }
+        if let Some(angle) = self.angle {
+            arg("angle", angle.to_string());
+        }
+        if let Some(bezier) = &self.bezier {
+            arg("bezier", bezier.to_string());
+        }
+        if let Some(wave) = &self.wave {
+            arg("wave", wave.to_string());
+        }
+        args
+    }
+}
+
+/// One of the transition types swww knows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionType(String);
+
+impl TransitionType {
+    /// Cross-fades from one wallpaper to the next.
+    pub fn fade() -> Self {
+        TransitionType("fade".to_string())
+    }
+}
+
+impl FromStr for TransitionType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_lowercase();
+        if TRANSITION_TYPES.contains(&value.as_str()) {
+            Ok(TransitionType(value))
+        } else {
+            Err(format!(
+                "Unknown transition type {}, expected one of {}.",
+                value,
+                TRANSITION_TYPES.join(", ")
+            ))
+        }
+    }
+}
+
+impl fmt::Display for TransitionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Where `grow` and `outer` start from: a named position, or `x,y` as pixels or fractions of the
+/// screen size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position(String);
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_lowercase();
+        if POSITIONS.contains(&value.as_str()) || parse_numbers::<2>(&value).is_some() {
+            Ok(Position(value.replace(' ', "")))
+        } else {
+            Err(format!(
+                "Invalid transition position {}, expected x,y or one of {}.",
+                value,
+                POSITIONS.join(", ")
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Easing curve given by the two control points of a cubic bezier, e.g. `.54,0,.34,.99`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bezier([f64; 4]);
+
+impl FromStr for Bezier {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        parse_numbers(value).map(Bezier).ok_or_else(|| {
+            format!(
+                "Invalid transition bezier {}, expected four numbers such as .54,0,.34,.99.",
+                value
+            )
+        })
+    }
+}
+
+impl fmt::Display for Bezier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [x1, y1, x2, y2] = self.0;
+        write!(f, "{},{},{},{}", x1, y1, x2, y2)
+    }
+}
+
+/// Width and height of the waves of the `wave` transition, e.g. `20,20`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wave([f64; 2]);
+
+impl FromStr for Wave {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        parse_numbers(value).map(Wave).ok_or_else(|| {
+            format!(
+                "Invalid transition wave {}, expected a width and a height such as 20,20.",
+                value
+            )
+        })
+    }
+}
+
+impl fmt::Display for Wave {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [width, height] = self.0;
+        write!(f, "{},{}", width, height)
+    }
+}
+
+/// Exactly `N` comma separated finite numbers.
+fn parse_numbers<const N: usize>(value: &str) -> Option<[f64; N]> {
+    let numbers = value
+        .split(',')
+        .map(|number| number.trim().parse::<f64>().ok().filter(|n| n.is_finite()))
+        .collect::<Option<Vec<_>>>()?;
+    numbers.try_into().ok()
+}
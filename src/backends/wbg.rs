@@ -0,0 +1,57 @@
+use std::path::Path;
+use std::process::Command;
+
+use super::{find_executable, replace_resident_process, ResidentProcesses, WallpaperBackend};
+use crate::error::Error;
+use crate::images::ImageFormat;
+
+const SUPPORTED_FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::Webp,
+    ImageFormat::JpegXl,
+];
+
+#[derive(Debug)]
+pub struct Wbg {
+    command: String,
+    residents: ResidentProcesses,
+}
+
+impl Wbg {
+    pub fn new(command: Option<String>) -> Self {
+        Wbg {
+            command: command.unwrap_or_else(|| "wbg".to_string()),
+            residents: ResidentProcesses::default(),
+        }
+    }
+}
+
+impl WallpaperBackend for Wbg {
+    fn name(&self) -> &'static str {
+        "wbg"
+    }
+
+    #[tracing::instrument]
+    fn apply(&self, _output: Option<&str>, selected_file: &Path) -> Result<(), Error> {
+        replace_resident_process(
+            self.name(),
+            None,
+            Command::new(&self.command).arg(selected_file),
+            &self.residents,
+        )
+    }
+
+    /// wbg always covers every output.
+    fn supports_outputs(&self) -> bool {
+        false
+    }
+
+    fn supported_formats(&self) -> &'static [ImageFormat] {
+        &SUPPORTED_FORMATS
+    }
+
+    fn check(&self) -> Result<String, String> {
+        find_executable(&self.command)
+    }
+}
@@ -0,0 +1,417 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use random_wallpaper::config::{self, Config};
+use random_wallpaper::marks::Rating;
+use random_wallpaper::selection::{SelectionMode, Strategy};
+use random_wallpaper::sources::Source;
+
+pub const USAGE: &str = "\
+Usage: random-wallpaper [OPTIONS] [COMMAND]
+
+Commands:
+  next            Change to a random wallpaper (default)
+  set <PATH>      Change to the given wallpaper
+  current         Print the current wallpaper of each output
+  previous        Go back to the wallpaper shown before the current one
+  list            Print every wallpaper that can be picked
+  history         Print the wallpapers applied so far, oldest first
+  favorite [PATH] Add the current wallpaper, or PATH, to the favourites
+  unfavorite [PATH]
+                  Remove the current wallpaper, or PATH, from the favourites
+  ban [PATH]      Never pick the current wallpaper, or PATH, again and change it
+  unban [PATH]    Let the current wallpaper, or PATH, be picked again
+  rate <1-5> [PATH]
+                  Rate the current wallpaper, or PATH, from 1 to 5 stars
+  unrate [PATH]   Remove the rating of the current wallpaper, or PATH
+  doctor          Check the configuration and the backend
+  daemon          Keep running and change the wallpaper every interval
+  pause           Stop the daemon from changing the wallpaper
+  resume          Let the daemon change the wallpaper again
+  set-interval <DURATION>
+                  Change the interval of the daemon
+  reload          Make the daemon read its configuration again
+  status          Print what the daemon is doing
+  help            Print this help
+
+Options:
+  -c, --config <PATH>          Config file to read
+  -b, --backend <NAME>         Backend: swww, swaybg, hyprpaper, wbg or custom
+      --command <COMMAND>      Command of the backend, or the template of the custom backend
+  -f, --folder <FOLDER>        Folder to pick from, optionally weighted with =weight, repeatable
+      --per-output             Pick a different wallpaper for each output
+  -m, --selection-mode <MODE>  random or shuffle
+  -s, --strategy <STRATEGY>    uniform, favorites or rating
+      --history-size <COUNT>   How many of the last wallpapers can't be picked again
+      --state-file <PATH>      State file to use
+      --no-notifications       Don't show desktop notifications
+  -i, --interval <DURATION>    Time between changes in daemon mode, e.g. 15m or 1h30m
+  -n, --dry-run                Print the chosen wallpaper without changing it
+  -h, --help                   Print this help
+  -V, --version                Print the version
+";
+
+/// Options that don't take a value.
+const FLAGS: [&str; 8] = [
+    "--per-output",
+    "--no-notifications",
+    "-n",
+    "--dry-run",
+    "-h",
+    "--help",
+    "-V",
+    "--version",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Next,
+    Set(PathBuf),
+    Current,
+    Previous,
+    List,
+    History,
+    Favorite(Option<PathBuf>),
+    Unfavorite(Option<PathBuf>),
+    Ban(Option<PathBuf>),
+    Unban(Option<PathBuf>),
+    Rate(Rating, Option<PathBuf>),
+    Unrate(Option<PathBuf>),
+    Doctor,
+    Daemon,
+    Pause,
+    Resume,
+    SetInterval(Duration),
+    Reload,
+    Status,
+    Help,
+    Version,
+}
+
+impl Command {
+    /// Whether the command only makes sense with a running daemon.
+    pub fn needs_daemon(&self) -> bool {
+        matches!(
+            self,
+            Command::Pause
+                | Command::Resume
+                | Command::SetInterval(_)
+                | Command::Reload
+                | Command::Status
+        )
+    }
+}
+
+/// Parsed command line. Options left unset keep the value from the config file or environment.
+#[derive(Debug)]
+pub struct Cli {
+    pub command: Command,
+    pub dry_run: bool,
+    pub config_file: Option<PathBuf>,
+    pub backend: Option<String>,
+    pub wallpaper_changer: Option<String>,
+    pub sources: Vec<Source>,
+    pub per_output: bool,
+    pub selection_mode: Option<SelectionMode>,
+    pub strategy: Option<Strategy>,
+    pub history_size: Option<usize>,
+    pub state_file: Option<PathBuf>,
+    pub no_notifications: bool,
+    pub interval: Option<Duration>,
+}
+
+impl Cli {
+    /// Parses the arguments after the program name. Options are accepted anywhere, as
+    /// `--option value` or `--option=value`.
+    pub fn parse(arguments: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+        let mut cli = Cli {
+            command: Command::Next,
+            dry_run: false,
+            config_file: None,
+            backend: None,
+            wallpaper_changer: None,
+            sources: Vec::new(),
+            per_output: false,
+            selection_mode: None,
+            strategy: None,
+            history_size: None,
+            state_file: None,
+            no_notifications: false,
+            interval: None,
+        };
+        let mut positional = Vec::new();
+
+        let mut arguments = arguments.into_iter();
+        while let Some(argument) = arguments.next() {
+            if !argument.starts_with('-') || argument == "-" {
+                positional.push(argument);
+                continue;
+            }
+            let (option, inline_value) = match argument.split_once('=') {
+                Some((option, value)) => (option.to_string(), Some(value.to_string())),
+                None => (argument.clone(), None),
+            };
+            let mut option_value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| arguments.next())
+                    .ok_or_else(|| format!("Missing value for {}.", option))
+            };
+            if inline_value.is_some() && FLAGS.contains(&option.as_str()) {
+                return Err(format!("{} doesn't take a value.", option));
+            }
+
+            match option.as_str() {
+                "-c" | "--config" => cli.config_file = Some(config::expand_path(&option_value()?)),
+                "-b" | "--backend" => cli.backend = Some(option_value()?),
+                "--command" => cli.wallpaper_changer = Some(option_value()?),
+                "-f" | "--folder" => cli.sources.push(option_value()?.parse()?),
+                "--per-output" => cli.per_output = true,
+                "-m" | "--selection-mode" => cli.selection_mode = Some(option_value()?.parse()?),
+                "-s" | "--strategy" => cli.strategy = Some(option_value()?.parse()?),
+                "--history-size" => {
+                    let value = option_value()?;
+                    cli.history_size = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid history size {}.", value))?,
+                    )
+                }
+                "--state-file" => cli.state_file = Some(config::expand_path(&option_value()?)),
+                "--no-notifications" => cli.no_notifications = true,
+                "-i" | "--interval" => {
+                    cli.interval = Some(config::parse_duration(&option_value()?)?)
+                }
+                "-n" | "--dry-run" => cli.dry_run = true,
+                "-h" | "--help" => cli.command = Command::Help,
+                "-V" | "--version" => cli.command = Command::Version,
+                _ => return Err(format!("Unknown option {}.", argument)),
+            }
+        }
+
+        if cli.command != Command::Next {
+            return Ok(cli);
+        }
+        let mut positional = positional.into_iter();
+        let path = |argument: Option<String>| argument.map(|path| config::expand_path(&path));
+        cli.command = match positional.next().as_deref() {
+            None | Some("next") => Command::Next,
+            Some("set") => {
+                Command::Set(path(positional.next()).ok_or("Missing the wallpaper to set.")?)
+            }
+            Some("current") => Command::Current,
+            Some("previous") => Command::Previous,
+            Some("list") => Command::List,
+            Some("history") => Command::History,
+            Some("favorite") => Command::Favorite(path(positional.next())),
+            Some("unfavorite") => Command::Unfavorite(path(positional.next())),
+            Some("ban") => Command::Ban(path(positional.next())),
+            Some("unban") => Command::Unban(path(positional.next())),
+            Some("rate") => Command::Rate(
+                positional.next().ok_or("Missing the rating.")?.parse()?,
+                path(positional.next()),
+            ),
+            Some("unrate") => Command::Unrate(path(positional.next())),
+            Some("doctor") => Command::Doctor,
+            Some("daemon") => Command::Daemon,
+            Some("pause") => Command::Pause,
+            Some("resume") => Command::Resume,
+            Some("set-interval") => Command::SetInterval(config::parse_duration(
+                &positional.next().ok_or("Missing the interval to set.")?,
+            )?),
+            Some("reload") => Command::Reload,
+            Some("status") => Command::Status,
+            Some("help") => Command::Help,
+            Some(command) => return Err(format!("Unknown command {}.", command)),
+        };
+        match positional.next() {
+            Some(argument) => Err(format!("Unexpected argument {}.", argument)),
+            None => Ok(cli),
+        }
+    }
+
+    /// The options given that change the config, which a running daemon can't take from a
+    /// request.
+    pub fn config_options(&self) -> Vec<&'static str> {
+        [
+            ("--config", self.config_file.is_some()),
+            ("--backend", self.backend.is_some()),
+            ("--command", self.wallpaper_changer.is_some()),
+            ("--folder", !self.sources.is_empty()),
+            ("--per-output", self.per_output),
+            ("--selection-mode", self.selection_mode.is_some()),
+            ("--strategy", self.strategy.is_some()),
+            ("--history-size", self.history_size.is_some()),
+            ("--state-file", self.state_file.is_some()),
+            ("--no-notifications", self.no_notifications),
+            ("--interval", self.interval.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(option, given)| given.then_some(option))
+        .collect()
+    }
+
+    /// Overrides the config with the options given on the command line.
+    pub fn apply(&self, config: &mut Config) {
+        if self.backend.is_some() {
+            config.backend = self.backend.clone();
+        }
+        if self.wallpaper_changer.is_some() {
+            config.command = self.wallpaper_changer.clone();
+        }
+        if !self.sources.is_empty() {
+            config.sources = self.sources.clone();
+        }
+        if self.per_output {
+            config.per_output = true;
+        }
+        if let Some(selection_mode) = self.selection_mode {
+            config.selection_mode = selection_mode;
+        }
+        if let Some(strategy) = self.strategy {
+            config.strategy = strategy;
+        }
+        if let Some(history_size) = self.history_size {
+            config.history_size = history_size;
+        }
+        if let Some(state_file) = &self.state_file {
+            config.state_file = state_file.clone();
+        }
+        if self.no_notifications {
+            config.notifications.enabled = false;
+        }
+        if let Some(interval) = self.interval {
+            config.interval = interval;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(arguments: &[&str]) -> Result<Cli, String> {
+        Cli::parse(arguments.iter().map(|argument| argument.to_string()))
+    }
+
+    #[test]
+    fn defaults_to_next() {
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.command, Command::Next);
+        assert!(cli.config_options().is_empty());
+    }
+
+    #[test]
+    fn parses_options_with_and_without_equals() {
+        let cli = parse(&[
+            "--backend=swaybg",
+            "-i",
+            "1h30m",
+            "-f",
+            "/walls=2",
+            "--folder=/more",
+            "--history-size",
+            "4",
+            "--per-output",
+            "-n",
+            "--command=feh --bg-fill {path}",
+            "--state-file=/tmp/state",
+        ])
+        .unwrap();
+        assert_eq!(cli.backend.as_deref(), Some("swaybg"));
+        assert_eq!(cli.interval, Some(Duration::from_secs(90 * 60)));
+        assert_eq!(cli.sources.len(), 2);
+        assert_eq!(cli.history_size, Some(4));
+        assert_eq!(
+            cli.wallpaper_changer.as_deref(),
+            Some("feh --bg-fill {path}")
+        );
+        assert_eq!(cli.state_file, Some(PathBuf::from("/tmp/state")));
+        assert!(cli.per_output && cli.dry_run);
+        assert_eq!(
+            cli.config_options(),
+            [
+                "--backend",
+                "--command",
+                "--folder",
+                "--per-output",
+                "--history-size",
+                "--state-file",
+                "--interval"
+            ]
+        );
+
+        let cli = parse(&["-m", "shuffle", "status", "--strategy=rating"]).unwrap();
+        assert_eq!(cli.command, Command::Status);
+        assert_eq!(cli.selection_mode, Some(SelectionMode::Shuffle));
+        assert_eq!(cli.strategy, Some(Strategy::Rating));
+    }
+
+    #[test]
+    fn parses_commands() {
+        let path = |path: &str| Some(PathBuf::from(path));
+        assert_eq!(
+            parse(&["set", "/a.png"]).unwrap().command,
+            Command::Set("/a.png".into())
+        );
+        assert_eq!(parse(&["ban"]).unwrap().command, Command::Ban(None));
+        assert_eq!(
+            parse(&["unfavorite", "/a.png"]).unwrap().command,
+            Command::Unfavorite(path("/a.png"))
+        );
+        assert_eq!(
+            parse(&["rate", "4", "/a.png"]).unwrap().command,
+            Command::Rate(Rating::new(4).unwrap(), path("/a.png"))
+        );
+        assert_eq!(
+            parse(&["set-interval", "15m"]).unwrap().command,
+            Command::SetInterval(Duration::from_secs(15 * 60))
+        );
+        assert_eq!(parse(&["status", "-h"]).unwrap().command, Command::Help);
+        assert_eq!(parse(&["-V"]).unwrap().command, Command::Version);
+        assert!(parse(&["pause"]).unwrap().command.needs_daemon());
+        assert!(!parse(&["next"]).unwrap().command.needs_daemon());
+    }
+
+    #[test]
+    fn expands_paths() {
+        let home = PathBuf::from(shellexpand::tilde("~").to_string());
+        let cli = parse(&["-c", "~/wallpaper.toml", "set", "~/a.png"]).unwrap();
+        assert_eq!(cli.config_file, Some(home.join("wallpaper.toml")));
+        assert_eq!(cli.command, Command::Set(home.join("a.png")));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        for (arguments, error) in [
+            (&["--backend"][..], "Missing value for --backend."),
+            (&["list", "-i"], "Missing value for -i."),
+            (&["--verbose"], "Unknown option --verbose."),
+            (&["--per-output=yes"], "--per-output doesn't take a value."),
+            (&["shuffle"], "Unknown command shuffle."),
+            (&["list", "extra"], "Unexpected argument extra."),
+            (&["ban", "/a.png", "/b.png"], "Unexpected argument /b.png."),
+            (&["set"], "Missing the wallpaper to set."),
+            (&["rate"], "Missing the rating."),
+            (&["set-interval"], "Missing the interval to set."),
+            (&["--history-size=-1"], "Invalid history size -1."),
+        ] {
+            assert_eq!(
+                parse(arguments).err().as_deref(),
+                Some(error),
+                "{:?}",
+                arguments
+            );
+        }
+
+        for arguments in [
+            &["rate", "0"][..],
+            &["rate", "6", "/a.png"],
+            &["rate", "four"],
+            &["set-interval", "0"],
+            &["-s", "best"],
+        ] {
+            assert!(parse(arguments).is_err(), "{:?}", arguments);
+        }
+    }
+}
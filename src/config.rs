@@ -0,0 +1,1198 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use heck::ToShoutySnakeCase;
+use toml_edit::{Document, Item, Table, Value};
+
+use crate::backends::{Fit, SwwwDaemon, Transition, TransitionType};
+use crate::filters::SizeFilter;
+use crate::images::{ImageFormat, ImageSize, ALL_FORMATS};
+use crate::notify::{self, Action, NotificationFilter, QuietHours, Template, Urgency};
+use crate::schedule::{Location, Period, Schedule, StartTime};
+use crate::selection::{RatingWeight, SelectionMode, Strategy};
+use crate::sources::{ScanOptions, Source};
+use crate::state;
+
+/// Longest duration accepted, e.g. for the interval, far below what an `Instant` can hold.
+pub const MAX_DURATION: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy)]
+pub enum EnvVar {
+    ConfigFile,
+    CacheFile,
+    StateFile,
+    WallpaperFolder,
+    WallpaperChanger,
+    WallpaperBackend,
+    WallpaperFit,
+    PerOutput,
+    MinResolution,
+    AspectRatioTolerance,
+    MatchOrientation,
+    ImageFormats,
+    MaxDepth,
+    FollowSymlinks,
+    SelectionMode,
+    HistorySize,
+    SelectionStrategy,
+    FavoriteWeight,
+    RatingWeight,
+    UnratedWeight,
+    TransitionType,
+    TransitionStep,
+    TransitionDuration,
+    TransitionFps,
+    TransitionPos,
+    TransitionAngle,
+    TransitionBezier,
+    TransitionWave,
+    SwwwStartDaemon,
+    SwwwDaemonTimeout,
+    CustomResident,
+    Notifications,
+    NotificationShow,
+    NotificationUrgency,
+    NotificationTimeout,
+    NotificationSummary,
+    NotificationBody,
+    NotificationActions,
+    QuietHours,
+    Interval,
+}
+
+impl fmt::Display for EnvVar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("RW_{:?}", self).to_shouty_snake_case())
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        err: io::Error,
+    },
+    /// A config file value, located by 1-based line and column.
+    Invalid {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    EnvVar {
+        env_var: EnvVar,
+        message: String,
+    },
+    /// Unknown backend or invalid custom command template.
+    Backend(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, err } => {
+                write!(f, "Failed to read {}: {}", path.display(), err)
+            }
+            ConfigError::Invalid {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path.display(), line, column, message),
+            ConfigError::EnvVar { env_var, message } => {
+                write!(f, "Invalid {}: {}", env_var, message)
+            }
+            ConfigError::Backend(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+#[derive(Debug, Clone)]
+pub struct NotificationConfig {
+    pub enabled: bool,
+    pub show: NotificationFilter,
+    /// Urgency of the notifications of new wallpapers, errors are always critical.
+    pub urgency: Urgency,
+    /// Milliseconds.
+    pub timeout: i32,
+    pub summary: Template,
+    pub body: Template,
+    /// When no new wallpaper is notified.
+    pub quiet_hours: Option<QuietHours>,
+    /// Buttons on the notifications of new wallpapers.
+    pub actions: Vec<Action>,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        NotificationConfig {
+            enabled: true,
+            show: NotificationFilter::All,
+            urgency: Urgency::Normal,
+            timeout: 3000,
+            summary: notify::APP_NAME.parse().expect("Invalid default summary"),
+            body: "{filename}".parse().expect("Invalid default body"),
+            quiet_hours: None,
+            actions: Action::DEFAULT.to_vec(),
+        }
+    }
+}
+
+/// Settings layered from defaults, the config file and `RW_*` environment variables, in that order.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub sources: Vec<Source>,
+    /// Backend name, inferred from `command` when unset.
+    pub backend: Option<String>,
+    /// Executable of the backend, or the argv template of the custom backend.
+    pub command: Option<String>,
+    /// How wallpapers are scaled by swaybg and the custom backend.
+    pub fit: Fit,
+    pub per_output: bool,
+    pub size_filter: SizeFilter,
+    pub image_formats: Vec<ImageFormat>,
+    pub scan_options: ScanOptions,
+    pub selection_mode: SelectionMode,
+    pub history_size: usize,
+    pub strategy: Strategy,
+    /// How many times likelier favourites are picked in random mode.
+    pub favorite_weight: f64,
+    pub rating_weight: RatingWeight,
+    /// Weight of unrated wallpapers, the one of a 3 star rating when unset.
+    pub unrated_weight: Option<f64>,
+    pub state_file: PathBuf,
+    /// Cache file of older versions, migrated into `state_file`.
+    pub cache_file: PathBuf,
+    pub transition: Transition,
+    pub swww_daemon: SwwwDaemon,
+    /// The custom command keeps running to draw the wallpaper.
+    pub custom_resident: bool,
+    pub notifications: NotificationConfig,
+    /// Time between changes in daemon mode.
+    pub interval: Duration,
+    /// Periods of the day picking from their own sources instead of `sources`.
+    pub schedule: Option<Schedule>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sources: vec![Source {
+                path: expand_path("~/Pictures/wallpapers"),
+                weight: 1.0,
+                tags: Vec::new(),
+            }],
+            backend: None,
+            command: None,
+            fit: Fit::default(),
+            per_output: false,
+            size_filter: SizeFilter::default(),
+            image_formats: ALL_FORMATS.to_vec(),
+            scan_options: ScanOptions {
+                max_depth: 0,
+                follow_symlinks: true,
+            },
+            selection_mode: SelectionMode::Random,
+            history_size: 1,
+            strategy: Strategy::Favorites,
+            favorite_weight: 2.0,
+            rating_weight: RatingWeight::Linear,
+            unrated_weight: None,
+            state_file: state::get_default_state_file_path(),
+            cache_file: expand_path("~/.wallpaper"),
+            transition: Transition::default(),
+            swww_daemon: SwwwDaemon::default(),
+            custom_resident: false,
+            notifications: NotificationConfig::default(),
+            interval: Duration::from_secs(30 * 60),
+            schedule: None,
+        }
+    }
+}
+
+/// `$RW_CONFIG_FILE`, or `$XDG_CONFIG_HOME/random-wallpaper/config.toml`.
+#[tracing::instrument]
+pub fn get_config_file_path() -> PathBuf {
+    match env::var(EnvVar::ConfigFile.to_string()) {
+        Ok(path) => expand_path(&path),
+        Err(_) => dirs::config_dir()
+            .unwrap_or_else(|| expand_path("~/.config"))
+            .join("random-wallpaper")
+            .join("config.toml"),
+    }
+}
+
+impl Config {
+    /// Reads `config_file_path`, or the default config file, which may be missing unless it was
+    /// asked for explicitly, and then applies the environment variables on top.
+    #[tracing::instrument]
+    pub fn load(config_file_path: Option<PathBuf>) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let explicit =
+            config_file_path.is_some() || env::var(EnvVar::ConfigFile.to_string()).is_ok();
+        let config_file_path = config_file_path.unwrap_or_else(get_config_file_path);
+        match fs::read_to_string(&config_file_path) {
+            Ok(content) => config.merge_file(&config_file_path, &content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !explicit => {}
+            Err(err) => {
+                return Err(ConfigError::Read {
+                    path: config_file_path,
+                    err,
+                })
+            }
+        }
+        config.merge_env()?;
+        Ok(config)
+    }
+
+    fn merge_file(&mut self, path: &Path, content: &str) -> Result<(), ConfigError> {
+        let document = parse_document(path, content)?;
+        let file = ConfigFile { path, content };
+        let root = file.table(document.as_table(), "", 0);
+        root.check_keys(&[
+            "sources",
+            "backend",
+            "command",
+            "fit",
+            "per_output",
+            "state_file",
+            "cache_file",
+            "scan",
+            "filters",
+            "selection",
+            "transition",
+            "swww",
+            "custom",
+            "notifications",
+            "daemon",
+            "schedule",
+        ])?;
+
+        if let Some(sources) = root.sources()? {
+            self.sources = sources;
+        }
+        set(&mut self.backend, root.parsed("backend")?.map(Some));
+        set(&mut self.command, root.parsed("command")?.map(Some));
+        set(&mut self.fit, root.parsed("fit")?);
+        set(&mut self.per_output, root.boolean("per_output")?);
+        set(&mut self.state_file, root.path("state_file")?);
+        set(&mut self.cache_file, root.path("cache_file")?);
+
+        if let Some(schedule) = root.child("schedule")? {
+            self.schedule = schedule.schedule()?;
+        }
+        if let Some(scan) = root.child("scan")? {
+            scan.check_keys(&["max_depth", "follow_symlinks", "image_formats"])?;
+            set(
+                &mut self.scan_options.max_depth,
+                scan.integer("max_depth", 0)?,
+            );
+            set(
+                &mut self.scan_options.follow_symlinks,
+                scan.boolean("follow_symlinks")?,
+            );
+            set(&mut self.image_formats, scan.list("image_formats")?);
+        }
+        if let Some(filters) = root.child("filters")? {
+            filters.check_keys(&[
+                "min_resolution",
+                "aspect_ratio_tolerance",
+                "match_orientation",
+            ])?;
+            set(
+                &mut self.size_filter.min_resolution,
+                filters.parsed::<ImageSize>("min_resolution")?.map(Some),
+            );
+            set(
+                &mut self.size_filter.aspect_ratio_tolerance,
+                filters.float("aspect_ratio_tolerance")?.map(Some),
+            );
+            set(
+                &mut self.size_filter.match_orientation,
+                filters.boolean("match_orientation")?,
+            );
+        }
+        if let Some(selection) = root.child("selection")? {
+            selection.check_keys(&[
+                "mode",
+                "history_size",
+                "strategy",
+                "favorite_weight",
+                "rating_weight",
+                "unrated_weight",
+            ])?;
+            set(&mut self.selection_mode, selection.parsed("mode")?);
+            set(
+                &mut self.history_size,
+                selection.integer("history_size", 0)?,
+            );
+            set(&mut self.strategy, selection.parsed("strategy")?);
+            set(
+                &mut self.favorite_weight,
+                selection.float("favorite_weight")?,
+            );
+            set(&mut self.rating_weight, selection.parsed("rating_weight")?);
+            set(
+                &mut self.unrated_weight,
+                selection.float("unrated_weight")?.map(Some),
+            );
+        }
+        if let Some(transition) = root.child("transition")? {
+            transition.check_keys(&[
+                "type", "step", "duration", "fps", "pos", "angle", "bezier", "wave",
+            ])?;
+            // A single type, or a list to pick a random one from for each change.
+            let types = match transition.value("type")? {
+                Some(value) if value.is_array() => transition.list("type")?,
+                _ => transition
+                    .parsed::<TransitionType>("type")?
+                    .map(|transition_type| vec![transition_type]),
+            };
+            set(&mut self.transition.types, types);
+            set(&mut self.transition.step, transition.integer("step", 1)?);
+            set(&mut self.transition.duration, transition.float("duration")?);
+            set(
+                &mut self.transition.fps,
+                transition.integer("fps", 1)?.map(Some),
+            );
+            set(
+                &mut self.transition.pos,
+                transition.parsed("pos")?.map(Some),
+            );
+            set(
+                &mut self.transition.angle,
+                transition.float("angle")?.map(Some),
+            );
+            set(
+                &mut self.transition.bezier,
+                transition.parsed("bezier")?.map(Some),
+            );
+            set(
+                &mut self.transition.wave,
+                transition.parsed("wave")?.map(Some),
+            );
+        }
+        if let Some(swww) = root.child("swww")? {
+            swww.check_keys(&["start_daemon", "daemon_timeout"])?;
+            set(&mut self.swww_daemon.start, swww.boolean("start_daemon")?);
+            set(
+                &mut self.swww_daemon.timeout,
+                swww.duration("daemon_timeout")?,
+            );
+        }
+        if let Some(custom) = root.child("custom")? {
+            custom.check_keys(&["resident"])?;
+            set(&mut self.custom_resident, custom.boolean("resident")?);
+        }
+        if let Some(notifications) = root.child("notifications")? {
+            notifications.check_keys(&[
+                "enabled",
+                "show",
+                "urgency",
+                "timeout",
+                "summary",
+                "body",
+                "quiet_hours",
+                "actions",
+            ])?;
+            set(
+                &mut self.notifications.enabled,
+                notifications.boolean("enabled")?,
+            );
+            set(&mut self.notifications.show, notifications.parsed("show")?);
+            set(
+                &mut self.notifications.urgency,
+                notifications.parsed("urgency")?,
+            );
+            set(
+                &mut self.notifications.timeout,
+                notifications.integer("timeout", -1)?,
+            );
+            set(
+                &mut self.notifications.summary,
+                notifications.parsed("summary")?,
+            );
+            set(&mut self.notifications.body, notifications.parsed("body")?);
+            set(
+                &mut self.notifications.quiet_hours,
+                notifications.parsed("quiet_hours")?.map(Some),
+            );
+            set(
+                &mut self.notifications.actions,
+                notifications.list("actions")?,
+            );
+        }
+        if let Some(daemon) = root.child("daemon")? {
+            daemon.check_keys(&["interval"])?;
+            set(&mut self.interval, daemon.duration("interval")?);
+        }
+        Ok(())
+    }
+
+    fn merge_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(value) = get_env_var(EnvVar::WallpaperFolder) {
+            self.sources = parse_env_list(EnvVar::WallpaperFolder, &value, ':')?;
+        }
+        set(
+            &mut self.backend,
+            get_env_var(EnvVar::WallpaperBackend).map(Some),
+        );
+        set(
+            &mut self.command,
+            get_env_var(EnvVar::WallpaperChanger).map(Some),
+        );
+        set(&mut self.fit, parse_env_var(EnvVar::WallpaperFit)?);
+        set(&mut self.per_output, get_env_flag(EnvVar::PerOutput));
+        set(
+            &mut self.state_file,
+            get_env_var(EnvVar::StateFile).map(|path| expand_path(&path)),
+        );
+        set(
+            &mut self.cache_file,
+            get_env_var(EnvVar::CacheFile).map(|path| expand_path(&path)),
+        );
+        set(
+            &mut self.scan_options.max_depth,
+            parse_env_integer(EnvVar::MaxDepth, 0)?,
+        );
+        set(
+            &mut self.scan_options.follow_symlinks,
+            get_env_flag(EnvVar::FollowSymlinks),
+        );
+        if let Some(value) = get_env_var(EnvVar::ImageFormats) {
+            self.image_formats = parse_env_list(EnvVar::ImageFormats, &value, ',')?;
+        }
+        set(
+            &mut self.size_filter.min_resolution,
+            parse_env_var(EnvVar::MinResolution)?.map(Some),
+        );
+        set(
+            &mut self.size_filter.aspect_ratio_tolerance,
+            parse_env_var(EnvVar::AspectRatioTolerance)?.map(Some),
+        );
+        set(
+            &mut self.size_filter.match_orientation,
+            get_env_flag(EnvVar::MatchOrientation),
+        );
+        set(
+            &mut self.selection_mode,
+            parse_env_var(EnvVar::SelectionMode)?,
+        );
+        set(
+            &mut self.history_size,
+            parse_env_integer(EnvVar::HistorySize, 0)?,
+        );
+        set(
+            &mut self.strategy,
+            parse_env_var(EnvVar::SelectionStrategy)?,
+        );
+        set(
+            &mut self.favorite_weight,
+            parse_env_weight(EnvVar::FavoriteWeight)?,
+        );
+        set(
+            &mut self.rating_weight,
+            parse_env_var(EnvVar::RatingWeight)?,
+        );
+        set(
+            &mut self.unrated_weight,
+            parse_env_weight(EnvVar::UnratedWeight)?.map(Some),
+        );
+        if let Some(value) = get_env_var(EnvVar::TransitionType) {
+            self.transition.types = parse_env_list(EnvVar::TransitionType, &value, ',')?;
+        }
+        set(
+            &mut self.transition.step,
+            parse_env_integer(EnvVar::TransitionStep, 1)?,
+        );
+        set(
+            &mut self.transition.duration,
+            parse_env_var(EnvVar::TransitionDuration)?,
+        );
+        set(
+            &mut self.transition.fps,
+            parse_env_integer(EnvVar::TransitionFps, 1)?.map(Some),
+        );
+        set(
+            &mut self.transition.pos,
+            parse_env_var(EnvVar::TransitionPos)?.map(Some),
+        );
+        set(
+            &mut self.transition.angle,
+            parse_env_var(EnvVar::TransitionAngle)?.map(Some),
+        );
+        set(
+            &mut self.transition.bezier,
+            parse_env_var(EnvVar::TransitionBezier)?.map(Some),
+        );
+        set(
+            &mut self.transition.wave,
+            parse_env_var(EnvVar::TransitionWave)?.map(Some),
+        );
+        set(
+            &mut self.swww_daemon.start,
+            get_env_flag(EnvVar::SwwwStartDaemon),
+        );
+        set(
+            &mut self.swww_daemon.timeout,
+            parse_env_duration(EnvVar::SwwwDaemonTimeout)?,
+        );
+        set(
+            &mut self.custom_resident,
+            get_env_flag(EnvVar::CustomResident),
+        );
+        set(
+            &mut self.notifications.enabled,
+            get_env_flag(EnvVar::Notifications),
+        );
+        set(
+            &mut self.notifications.show,
+            parse_env_var(EnvVar::NotificationShow)?,
+        );
+        set(
+            &mut self.notifications.urgency,
+            parse_env_var(EnvVar::NotificationUrgency)?,
+        );
+        set(
+            &mut self.notifications.timeout,
+            parse_env_integer(EnvVar::NotificationTimeout, -1)?,
+        );
+        set(
+            &mut self.notifications.summary,
+            parse_env_var(EnvVar::NotificationSummary)?,
+        );
+        set(
+            &mut self.notifications.body,
+            parse_env_var(EnvVar::NotificationBody)?,
+        );
+        set(
+            &mut self.notifications.quiet_hours,
+            parse_env_var(EnvVar::QuietHours)?.map(Some),
+        );
+        if let Some(value) = get_env_var(EnvVar::NotificationActions) {
+            self.notifications.actions = parse_env_list(EnvVar::NotificationActions, &value, ',')?;
+        }
+        set(&mut self.interval, parse_env_duration(EnvVar::Interval)?);
+        Ok(())
+    }
+}
+
+/// Parses durations such as `90`, `15m` or `1h30m`, where numbers without a unit are seconds,
+/// up to `MAX_DURATION`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid duration {}, expected e.g. 30s, 15m or 1h30m.",
+            value
+        )
+    };
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    let mut add = |number: &str, unit: u64| {
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit))
+            .and_then(|seconds| total.checked_add(seconds))
+            .filter(|sum| *sum <= MAX_DURATION.as_secs())
+            .map(|sum| total = sum)
+            .ok_or_else(invalid)
+    };
+    for character in value.trim().chars() {
+        if character.is_ascii_digit() {
+            number.push(character);
+            continue;
+        }
+        let unit = match character {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        add(&number, unit)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        add(&number, 1)?;
+    }
+    if total == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Formats durations the way `parse_duration` reads them, e.g. `1h30m`.
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    let mut formatted = String::new();
+    for (unit, length) in [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)] {
+        if seconds >= length {
+            formatted.push_str(&format!("{}{}", seconds / length, unit));
+            seconds %= length;
+        }
+    }
+    if formatted.is_empty() {
+        formatted.push_str("0s");
+    }
+    formatted
+}
+
+/// Parses a local time of day such as `22:00` or `7:30`, as seconds since midnight.
+pub fn parse_time_of_day(value: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid time {}, expected HH:MM.", value.trim());
+    let (hours, minutes) = value.trim().split_once(':').ok_or_else(invalid)?;
+    let hours = hours.parse::<u32>().map_err(|_| invalid())?;
+    let minutes = minutes.parse::<u32>().map_err(|_| invalid())?;
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+        return Err(invalid());
+    }
+    Ok(hours * 60 * 60 + minutes * 60)
+}
+
+fn set<T>(setting: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *setting = value;
+    }
+}
+
+/// Expands a leading `~` to the home directory.
+pub fn expand_path(path: &str) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(path).to_string())
+}
+
+fn get_env_var(env_var: EnvVar) -> Option<String> {
+    env::var(env_var.to_string()).ok()
+}
+
+fn get_env_flag(env_var: EnvVar) -> Option<bool> {
+    get_env_var(env_var).map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+fn parse_env_var<T>(env_var: EnvVar) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    get_env_var(env_var)
+        .map(|value| {
+            value.trim().parse().map_err(|err| ConfigError::EnvVar {
+                env_var,
+                message: format!("{}", err),
+            })
+        })
+        .transpose()
+}
+
+/// An integer of at least `min`.
+fn parse_env_integer<T: TryFrom<i64>>(env_var: EnvVar, min: i64) -> Result<Option<T>, ConfigError> {
+    parse_env_var::<i64>(env_var)?
+        .map(|integer| {
+            if integer < min {
+                return Err(ConfigError::EnvVar {
+                    env_var,
+                    message: format!("Expected an integer of at least {}.", min),
+                });
+            }
+            T::try_from(integer).map_err(|_| ConfigError::EnvVar {
+                env_var,
+                message: format!("{} is too large.", integer),
+            })
+        })
+        .transpose()
+}
+
+/// A weight, which can't be negative.
+fn parse_env_weight(env_var: EnvVar) -> Result<Option<f64>, ConfigError> {
+    match parse_env_var::<f64>(env_var)? {
+        Some(weight) if !weight.is_finite() || weight < 0.0 => Err(ConfigError::EnvVar {
+            env_var,
+            message: "Expected a positive number.".to_string(),
+        }),
+        weight => Ok(weight),
+    }
+}
+
+fn parse_env_duration(env_var: EnvVar) -> Result<Option<Duration>, ConfigError> {
+    get_env_var(env_var)
+        .map(|value| {
+            parse_duration(&value).map_err(|message| ConfigError::EnvVar { env_var, message })
+        })
+        .transpose()
+}
+
+fn parse_env_list<T>(env_var: EnvVar, value: &str, separator: char) -> Result<Vec<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .split(separator)
+        .filter(|item| !item.trim().is_empty())
+        .map(|item| {
+            item.trim().parse().map_err(|err| ConfigError::EnvVar {
+                env_var,
+                message: format!("{}", err),
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn parse_document(path: &Path, content: &str) -> Result<Document, ConfigError> {
+    content.parse::<Document>().map_err(|err| {
+        let (line, column) = position(content, err.span().map_or(0, |span| span.start));
+        ConfigError::Invalid {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message: err.message().trim().replace('\n', "; "),
+        }
+    })
+}
+
+/// 1-based line and column of a byte offset.
+pub fn position(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Name of the table a `[header]` or `[[header]]` line opens, with dotted parts unquoted.
+fn table_header(line: &str) -> Option<String> {
+    let header = line.strip_prefix('[')?;
+    let header = header.strip_prefix('[').unwrap_or(header);
+    let (header, _) = header.split_once(']')?;
+    Some(
+        header
+            .split('.')
+            .map(|part| part.trim().trim_matches(|c| c == '"' || c == '\''))
+            .collect::<Vec<_>>()
+            .join("."),
+    )
+}
+
+/// Spans are dropped when the document is parsed, so values are found again by scanning the lines
+/// for `key =` within the `index`th occurrence of the `header` table, or for a `[header.key]` table.
+/// Falls back to the start of the table, or of the file.
+fn locate(content: &str, header: &str, index: usize, key: &str) -> (usize, usize) {
+    let child_header = if header.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", header, key)
+    };
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    let mut current = (String::new(), 0);
+    let mut table_start = (1, 1);
+    for (number, line) in content.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let indent = line.chars().count() - trimmed.chars().count();
+        if let Some(name) = table_header(trimmed) {
+            if name == child_header {
+                return (number + 1, indent + 1);
+            }
+            let occurrence = occurrences.entry(name.clone()).or_insert(0);
+            current = (name, *occurrence);
+            *occurrence += 1;
+            if current.0 == header && current.1 == index {
+                table_start = (number + 1, indent + 1);
+            }
+            continue;
+        }
+        if current.0 != header || current.1 != index || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some((line_key, value)) = trimmed.split_once('=') {
+            if line_key.trim().trim_matches(|c| c == '"' || c == '\'') == key {
+                let column = line.chars().count() - value.trim_start().chars().count();
+                return (number + 1, column + 1);
+            }
+        }
+    }
+    table_start
+}
+
+pub(crate) struct ConfigFile<'a> {
+    pub(crate) path: &'a Path,
+    pub(crate) content: &'a str,
+}
+
+impl<'a> ConfigFile<'a> {
+    pub(crate) fn table(
+        &'a self,
+        table: &'a Table,
+        header: &'a str,
+        index: usize,
+    ) -> TableReader<'a> {
+        TableReader {
+            file: self,
+            table,
+            header,
+            index,
+        }
+    }
+}
+
+/// Typed access to the keys of one table in the config file, reporting where a bad value is.
+pub(crate) struct TableReader<'a> {
+    file: &'a ConfigFile<'a>,
+    table: &'a Table,
+    header: &'a str,
+    index: usize,
+}
+
+impl<'a> TableReader<'a> {
+    pub(crate) fn invalid(&self, key: &str, message: String) -> ConfigError {
+        let (line, column) = locate(self.file.content, self.header, self.index, key);
+        ConfigError::Invalid {
+            path: self.file.path.to_path_buf(),
+            line,
+            column,
+            message,
+        }
+    }
+
+    pub(crate) fn check_keys(&self, known_keys: &[&str]) -> Result<(), ConfigError> {
+        match self.table.iter().find(|(key, _)| !known_keys.contains(key)) {
+            Some((key, _)) => Err(self.invalid(key, format!("Unknown key {}.", key))),
+            None => Ok(()),
+        }
+    }
+
+    fn value(&self, key: &str) -> Result<Option<&'a Value>, ConfigError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Item::Value(value)) => Ok(Some(value)),
+            Some(_) => Err(self.invalid(key, format!("Expected a value for {}.", key))),
+        }
+    }
+
+    fn child(&self, key: &'a str) -> Result<Option<TableReader<'a>>, ConfigError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Item::Table(table)) => Ok(Some(self.file.table(table, key, 0))),
+            Some(_) => Err(self.invalid(key, format!("Expected a [{}] table.", key))),
+        }
+    }
+
+    fn boolean(&self, key: &str) -> Result<Option<bool>, ConfigError> {
+        self.value(key)?
+            .map(|value| {
+                value.as_bool().ok_or_else(|| {
+                    self.invalid(key, format!("Expected true or false for {}.", key))
+                })
+            })
+            .transpose()
+    }
+
+    /// An integer of at least `min`.
+    fn integer<T: TryFrom<i64>>(&self, key: &str, min: i64) -> Result<Option<T>, ConfigError> {
+        self.value(key)?
+            .map(|value| {
+                let integer = value
+                    .as_integer()
+                    .filter(|integer| *integer >= min)
+                    .ok_or_else(|| {
+                        self.invalid(
+                            key,
+                            format!("Expected an integer of at least {} for {}.", min, key),
+                        )
+                    })?;
+                T::try_from(integer).map_err(|_| {
+                    self.invalid(key, format!("{} is too large for {}.", integer, key))
+                })
+            })
+            .transpose()
+    }
+
+    /// A latitude or longitude, within `-limit..=limit` degrees.
+    fn coordinate(&self, key: &str, limit: f64) -> Result<Option<f64>, ConfigError> {
+        self.value(key)?
+            .map(|value| {
+                value
+                    .as_float()
+                    .or_else(|| value.as_integer().map(|integer| integer as f64))
+                    .filter(|float| (-limit..=limit).contains(float))
+                    .ok_or_else(|| {
+                        self.invalid(
+                            key,
+                            format!(
+                                "Expected a number from -{} to {} for {}.",
+                                limit, limit, key
+                            ),
+                        )
+                    })
+            })
+            .transpose()
+    }
+
+    /// `[schedule]` with a `latitude` and `longitude` for the sun, and `[[schedule.periods]]`.
+    fn schedule(&self) -> Result<Option<Schedule>, ConfigError> {
+        self.check_keys(&["latitude", "longitude", "periods"])?;
+        let location = match (
+            self.coordinate("latitude", 90.0)?,
+            self.coordinate("longitude", 180.0)?,
+        ) {
+            (Some(latitude), Some(longitude)) => Some(Location {
+                latitude,
+                longitude,
+            }),
+            (None, None) => None,
+            (Some(_), None) => {
+                return Err(self.invalid("latitude", "Missing longitude.".to_string()))
+            }
+            (None, Some(_)) => {
+                return Err(self.invalid("longitude", "Missing latitude.".to_string()))
+            }
+        };
+        let tables = match self.table.get("periods") {
+            // The location alone is still used by the dynamic sets.
+            None => {
+                return Ok(location.map(|location| Schedule {
+                    location: Some(location),
+                    periods: Vec::new(),
+                }))
+            }
+            Some(Item::ArrayOfTables(tables)) => tables,
+            Some(_) => {
+                return Err(self.invalid(
+                    "periods",
+                    "Expected [[schedule.periods]] tables.".to_string(),
+                ))
+            }
+        };
+        let periods = tables
+            .iter()
+            .enumerate()
+            .map(|(index, table)| {
+                let period = self.file.table(table, "schedule.periods", index);
+                period.check_keys(&["name", "start", "sources", "tags"])?;
+                let start: StartTime = period.parsed("start")?.ok_or_else(|| {
+                    period.invalid("start", "Missing start for period.".to_string())
+                })?;
+                if location.is_none() && start.uses_sun() {
+                    return Err(period.invalid(
+                        "start",
+                        "Sunrise and sunset need the latitude and longitude of [schedule]."
+                            .to_string(),
+                    ));
+                }
+                let sources = period.list("sources")?.unwrap_or_default();
+                let tags = period.list("tags")?.unwrap_or_default();
+                if sources.is_empty() && tags.is_empty() {
+                    return Err(
+                        period.invalid("start", "Expected sources or tags for period.".to_string())
+                    );
+                }
+                Ok(Period {
+                    name: period.parsed("name")?,
+                    start,
+                    sources,
+                    tags,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((location.is_some() || !periods.is_empty()).then_some(Schedule { location, periods }))
+    }
+
+    fn float(&self, key: &str) -> Result<Option<f64>, ConfigError> {
+        self.value(key)?
+            .map(|value| {
+                value
+                    .as_float()
+                    .or_else(|| value.as_integer().map(|integer| integer as f64))
+                    .filter(|float| float.is_finite() && *float >= 0.0)
+                    .ok_or_else(|| {
+                        self.invalid(key, format!("Expected a positive number for {}.", key))
+                    })
+            })
+            .transpose()
+    }
+
+    fn string(&self, key: &str) -> Result<Option<&'a str>, ConfigError> {
+        self.value(key)?
+            .map(|value| {
+                value
+                    .as_str()
+                    .ok_or_else(|| self.invalid(key, format!("Expected a string for {}.", key)))
+            })
+            .transpose()
+    }
+
+    pub(crate) fn duration(&self, key: &str) -> Result<Option<Duration>, ConfigError> {
+        self.string(key)?
+            .map(|value| parse_duration(value).map_err(|message| self.invalid(key, message)))
+            .transpose()
+    }
+
+    pub(crate) fn path(&self, key: &str) -> Result<Option<PathBuf>, ConfigError> {
+        Ok(self.string(key)?.map(expand_path))
+    }
+
+    pub(crate) fn parsed<T>(&self, key: &str) -> Result<Option<T>, ConfigError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.string(key)?
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|err| self.invalid(key, format!("{}", err)))
+            })
+            .transpose()
+    }
+
+    /// An array of strings, each parsed on its own.
+    pub(crate) fn list<T>(&self, key: &str) -> Result<Option<Vec<T>>, ConfigError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let Some(value) = self.value(key)? else {
+            return Ok(None);
+        };
+        let Some(array) = value.as_array() else {
+            return Err(self.invalid(key, format!("Expected an array for {}.", key)));
+        };
+        array
+            .iter()
+            .map(|item| match item.as_str() {
+                Some(item) => item
+                    .parse()
+                    .map_err(|err| self.invalid(key, format!("{}", err))),
+                None => Err(self.invalid(key, format!("Expected strings in {}.", key))),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+    }
+
+    /// Sources are either `sources = ["path=weight", ...]` or `[[sources]]` tables with a `path`,
+    /// an optional `weight` and optional `tags`.
+    fn sources(&self) -> Result<Option<Vec<Source>>, ConfigError> {
+        let Some(Item::ArrayOfTables(tables)) = self.table.get("sources") else {
+            return self.list("sources");
+        };
+        tables
+            .iter()
+            .enumerate()
+            .map(|(index, table)| {
+                let source = self.file.table(table, "sources", index);
+                source.check_keys(&["path", "weight", "tags"])?;
+                let path = source.path("path")?.ok_or_else(|| {
+                    source.invalid("path", "Missing path for source.".to_string())
+                })?;
+                Ok(Source {
+                    path,
+                    weight: source.float("weight")?.unwrap_or(1.0),
+                    tags: source.list("tags")?.unwrap_or_default(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_durations_the_way_they_are_parsed() {
+        for (value, seconds) in [
+            ("90", 90),
+            ("30s", 30),
+            ("15m", 15 * 60),
+            ("1h30m", 90 * 60),
+            ("2d", 2 * 24 * 60 * 60),
+            ("1d2h3m4s", 93784),
+        ] {
+            let duration = parse_duration(value).unwrap();
+            assert_eq!(duration, Duration::from_secs(seconds), "{}", value);
+            assert_eq!(parse_duration(&format_duration(duration)), Ok(duration));
+        }
+        assert_eq!(format_duration(Duration::from_secs(90)), "1m30s");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        let overflows = ["9999999999999999h", "18446744073709551616", "36501d"];
+        for value in ["", "0", "0s", "5x", "m", "1h-5m", "1m 30s"]
+            .into_iter()
+            .chain(overflows)
+        {
+            assert!(parse_duration(value).is_err(), "{}", value);
+        }
+        assert_eq!(
+            parse_duration("5x"),
+            Err("Invalid duration 5x, expected e.g. 30s, 15m or 1h30m.".to_string())
+        );
+        assert_eq!(parse_duration("36500d"), Ok(MAX_DURATION));
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!(parse_time_of_day("7:30"), Ok(7 * 3600 + 30 * 60));
+        assert_eq!(parse_time_of_day(" 22:00 "), Ok(22 * 3600));
+        assert_eq!(parse_time_of_day("24:00"), Ok(24 * 3600));
+        for value in ["24:01", "12:60", "1230", "12:", "-1:00", "noon"] {
+            assert!(parse_time_of_day(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn reports_positions() {
+        let content = "a = 1\nbé = 2\n";
+        assert_eq!(position(content, 0), (1, 1));
+        assert_eq!(position(content, 6), (2, 1));
+        assert_eq!(position(content, 9), (2, 3));
+        assert_eq!(position(content, 1000), (3, 1));
+    }
+
+    #[test]
+    fn locates_keys() {
+        let content = "\
+interval = \"30m\"
+
+[[sources]]
+path = \"~/a\"
+
+[[sources]]
+  path = \"~/b\"
+# weight = 1
+weight = 2
+
+[notifications.quiet_hours]
+start = \"22:00\"
+";
+        assert_eq!(locate(content, "", 0, "interval"), (1, 12));
+        assert_eq!(locate(content, "sources", 1, "path"), (7, 10));
+        assert_eq!(locate(content, "sources", 1, "weight"), (9, 10));
+        // Missing keys point at their table, keys naming a table at its header.
+        assert_eq!(locate(content, "sources", 0, "weight"), (3, 1));
+        assert_eq!(locate(content, "notifications", 0, "quiet_hours"), (11, 1));
+        assert_eq!(locate(content, "", 0, "missing"), (1, 1));
+    }
+
+    #[test]
+    fn reports_where_a_value_is_invalid() {
+        let content = "[selection]\nmode = \"random\"\nhistory_size = -3\n";
+        let err = Config::default()
+            .merge_file(Path::new("config.toml"), content)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "config.toml:3:16: Expected an integer of at least 0 for history_size."
+        );
+    }
+}
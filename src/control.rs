@@ -0,0 +1,282 @@
+//! The requests a running daemon takes, and the control socket they are sent through.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::config;
+use crate::error::Error;
+use crate::marks::Rating;
+use crate::notify::Action;
+
+const SOCKET_NAME: &str = "random-wallpaper.sock";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Changing the wallpaper can take a while, e.g. when a backend needs to start first.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Operations a running daemon can be asked to do, sent over the control socket as one line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Next,
+    Previous,
+    Set(PathBuf),
+    Current,
+    Favorite(Option<PathBuf>),
+    Unfavorite(Option<PathBuf>),
+    Ban(Option<PathBuf>),
+    Unban(Option<PathBuf>),
+    Rate(Rating, Option<PathBuf>),
+    Unrate(Option<PathBuf>),
+    Pause,
+    Resume,
+    SetInterval(Duration),
+    Reload,
+    Status,
+}
+
+/// Lines to print on success, or what went wrong.
+pub type Response = Result<Vec<String>, Failure>;
+
+/// Why a request failed, with the code the command that sent it exits with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub exit_code: i32,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure {
+            exit_code: 1,
+            message,
+        }
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        Failure {
+            exit_code: err.exit_code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl Request {
+    /// What `action` clicked for `path` on a notification asks for, `None` for `Open` which needs
+    /// no daemon.
+    pub fn from_action(action: Action, path: PathBuf) -> Option<Request> {
+        match action {
+            Action::Next => Some(Request::Next),
+            Action::Keep => Some(Request::Favorite(Some(path))),
+            Action::NeverAgain => Some(Request::Ban(Some(path))),
+            Action::Rate(rating) => Some(Request::Rate(rating, Some(path))),
+            Action::Open => None,
+        }
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::Next => write!(f, "next"),
+            Request::Previous => write!(f, "previous"),
+            Request::Set(path) => write!(f, "set {}", path.display()),
+            Request::Current => write!(f, "current"),
+            Request::Favorite(None) => write!(f, "favorite"),
+            Request::Favorite(Some(path)) => write!(f, "favorite {}", path.display()),
+            Request::Unfavorite(None) => write!(f, "unfavorite"),
+            Request::Unfavorite(Some(path)) => write!(f, "unfavorite {}", path.display()),
+            Request::Ban(None) => write!(f, "ban"),
+            Request::Ban(Some(path)) => write!(f, "ban {}", path.display()),
+            Request::Unban(None) => write!(f, "unban"),
+            Request::Unban(Some(path)) => write!(f, "unban {}", path.display()),
+            Request::Rate(rating, None) => write!(f, "rate {}", rating),
+            Request::Rate(rating, Some(path)) => write!(f, "rate {} {}", rating, path.display()),
+            Request::Unrate(None) => write!(f, "unrate"),
+            Request::Unrate(Some(path)) => write!(f, "unrate {}", path.display()),
+            Request::Pause => write!(f, "pause"),
+            Request::Resume => write!(f, "resume"),
+            Request::SetInterval(interval) => {
+                write!(f, "set-interval {}", config::format_duration(*interval))
+            }
+            Request::Reload => write!(f, "reload"),
+            Request::Status => write!(f, "status"),
+        }
+    }
+}
+
+/// Parses a request line: the command, then its argument separated by a space.
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (command, argument) = match line.split_once(' ') {
+            Some((command, argument)) => (command, Some(argument)),
+            None => (line, None),
+        };
+        let path = argument.map(PathBuf::from);
+        match (command, argument) {
+            ("next", None) => Ok(Request::Next),
+            ("previous", None) => Ok(Request::Previous),
+            ("set", Some(_)) => Ok(Request::Set(path.unwrap_or_default())),
+            ("current", None) => Ok(Request::Current),
+            ("favorite", _) => Ok(Request::Favorite(path)),
+            ("unfavorite", _) => Ok(Request::Unfavorite(path)),
+            ("ban", _) => Ok(Request::Ban(path)),
+            ("unban", _) => Ok(Request::Unban(path)),
+            ("rate", Some(argument)) => {
+                let (rating, path) = match argument.split_once(' ') {
+                    Some((rating, path)) => (rating, Some(PathBuf::from(path))),
+                    None => (argument, None),
+                };
+                Ok(Request::Rate(rating.parse()?, path))
+            }
+            ("unrate", _) => Ok(Request::Unrate(path)),
+            ("pause", None) => Ok(Request::Pause),
+            ("resume", None) => Ok(Request::Resume),
+            ("set-interval", Some(interval)) => {
+                config::parse_duration(interval).map(Request::SetInterval)
+            }
+            ("reload", None) => Ok(Request::Reload),
+            ("status", None) => Ok(Request::Status),
+            _ => Err(format!("Invalid request {}.", line)),
+        }
+    }
+}
+
+/// Requests handled by the daemon loop, in the order they arrive.
+#[derive(Debug)]
+pub enum Event {
+    /// Reads the config file and the state again.
+    Reload,
+    Shutdown,
+    Request(Request, Sender<Response>),
+}
+
+/// `$XDG_RUNTIME_DIR/random-wallpaper.sock`.
+#[tracing::instrument]
+pub fn get_socket_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(SOCKET_NAME)
+}
+
+/// Binds the control socket, replacing a stale one, and forwards every request to the daemon
+/// loop from a dedicated thread. Fails when another daemon is already listening.
+///
+/// Each connection carries a single request line such as `next` or `set-interval 15m`. The reply
+/// is `ok` followed by the lines to print, or `error <exit code> <message>`.
+#[tracing::instrument(skip(sender))]
+pub fn listen(socket_path: &Path, sender: Sender<Event>) -> io::Result<()> {
+    if UnixStream::connect(socket_path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another daemon is listening on {}", socket_path.display()),
+        ));
+    }
+    match fs::remove_file(socket_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let listener = UnixListener::bind(socket_path)?;
+    info!("Listening on {}", socket_path.display());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| handle_connection(stream, &sender));
+            if let Err(err) = result {
+                warn!("Failed to handle a control connection: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[tracing::instrument(skip(sender))]
+fn handle_connection(mut stream: UnixStream, sender: &Sender<Event>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = line
+        .parse::<Request>()
+        .map_err(Failure::from)
+        .and_then(|request| {
+            let (reply_sender, reply) = mpsc::channel();
+            sender
+                .send(Event::Request(request, reply_sender))
+                .map_err(|_| Failure::from("The daemon is shutting down.".to_string()))?;
+            reply
+                .recv_timeout(RESPONSE_TIMEOUT)
+                .map_err(|_| Failure::from("The daemon did not answer in time.".to_string()))?
+        });
+
+    match response {
+        Ok(lines) => {
+            writeln!(stream, "ok")?;
+            for line in lines {
+                writeln!(stream, "{}", line)?;
+            }
+        }
+        Err(failure) => writeln!(stream, "error {} {}", failure.exit_code, failure.message)?,
+    }
+    Ok(())
+}
+
+/// Whether a daemon is listening on `socket_path`.
+pub fn is_daemon_running(socket_path: &Path) -> bool {
+    UnixStream::connect(socket_path).is_ok()
+}
+
+/// Sends `request` to a running daemon. Fails with `NotFound` or `ConnectionRefused` when there
+/// is none.
+#[tracing::instrument]
+pub fn send(socket_path: &Path, request: &Request) -> io::Result<Response> {
+    let mut stream = UnixStream::connect(socket_path)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    writeln!(stream, "{}", request)?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let status = lines.next().transpose()?.unwrap_or_default();
+    if status == "ok" {
+        return Ok(Ok(lines.collect::<io::Result<Vec<_>>>()?));
+    }
+    match status.strip_prefix("error ") {
+        Some(failure) => Ok(Err(parse_failure(failure))),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected reply {:?}", status),
+        )),
+    }
+}
+
+/// Reads `<exit code> <message>`, taking the whole line as the message without a code.
+fn parse_failure(failure: &str) -> Failure {
+    failure
+        .split_once(' ')
+        .and_then(|(exit_code, message)| {
+            Some(Failure {
+                exit_code: exit_code.parse().ok()?,
+                message: message.to_string(),
+            })
+        })
+        .unwrap_or_else(|| Failure::from(failure.to_string()))
+}
@@ -0,0 +1,424 @@
+use std::fs;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{SigSet, Signal};
+use tracing::{error, info, warn};
+
+use random_wallpaper::backends::WallpaperBackend;
+use random_wallpaper::config::{self, Config};
+use random_wallpaper::control::{self, Event, Failure, Request};
+use random_wallpaper::dbus::{self, Service, Snapshot};
+use random_wallpaper::error::Error;
+use random_wallpaper::notify;
+use random_wallpaper::schedule;
+use random_wallpaper::sources::{self, Libraries};
+use random_wallpaper::state::{self, State};
+
+use crate::cli::Cli;
+
+/// How long a scan of the wallpaper folders is reused before scanning them again.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Blocks SIGTERM, SIGINT and SIGHUP and turns them into events from a dedicated thread. Must run
+/// before any other thread is started so they all inherit the signal mask.
+#[tracing::instrument(skip(sender))]
+pub fn forward_signals(sender: Sender<Event>) -> nix::Result<()> {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGTERM);
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGHUP);
+    signals.thread_block()?;
+
+    thread::spawn(move || loop {
+        let event = match signals.wait() {
+            Ok(Signal::SIGHUP) => Event::Reload,
+            Ok(signal) => {
+                info!("Received {}", signal);
+                Event::Shutdown
+            }
+            Err(err) => {
+                error!("Failed to wait for signals: {}", err);
+                Event::Shutdown
+            }
+        };
+        let shutdown = matches!(event, Event::Shutdown);
+        if sender.send(event).is_err() || shutdown {
+            break;
+        }
+    });
+    Ok(())
+}
+
+/// Everything the daemon keeps between changes.
+struct Daemon<'a> {
+    cli: &'a Cli,
+    config: Config,
+    backend: Box<dyn WallpaperBackend>,
+    state: State,
+    libraries: Option<Libraries>,
+    paused: bool,
+    next_change: Instant,
+    /// Missing when the session bus can't be reached.
+    dbus: Option<Service>,
+}
+
+/// Changes the wallpaper right away and then every interval, until SIGTERM or SIGINT. SIGHUP
+/// reloads the config and the state, and requests arrive through the control socket, D-Bus and
+/// the actions of the notifications.
+#[tracing::instrument(skip_all)]
+pub fn run(cli: &Cli, config: Config, backend: Box<dyn WallpaperBackend>, state: State) {
+    let (sender, events) = mpsc::channel();
+    if let Err(err) = forward_signals(sender.clone()) {
+        error!("Failed to set up signal handling: {}", err);
+        return;
+    }
+    let socket_path = control::get_socket_path();
+    if let Err(err) = control::listen(&socket_path, sender.clone()) {
+        error!("Failed to listen on {}: {}", socket_path.display(), err);
+        return;
+    }
+    let action_sender = sender.clone();
+    notify::set_action_handler(move |action, path| {
+        let Some(request) = action.and_then(|action| Request::from_action(action, path)) else {
+            return;
+        };
+        let (reply_sender, reply) = mpsc::channel();
+        // Failures are reported by the daemon, waiting only keeps the reply from being dropped.
+        if action_sender
+            .send(Event::Request(request, reply_sender))
+            .is_ok()
+        {
+            let _ = reply.recv();
+        }
+    });
+    let snapshot = Snapshot::new(&state, false, config.interval);
+    let dbus = match zbus::blocking::Connection::session()
+        .and_then(|connection| dbus::serve(connection, sender, snapshot))
+    {
+        Ok(service) => Some(service),
+        Err(err) => {
+            warn!("Not available on D-Bus: {}", err);
+            None
+        }
+    };
+
+    let mut daemon = Daemon {
+        cli,
+        config,
+        backend,
+        state,
+        libraries: None,
+        paused: false,
+        next_change: Instant::now(),
+        dbus,
+    };
+    info!(
+        "Changing the wallpaper every {}",
+        config::format_duration(daemon.config.interval)
+    );
+    loop {
+        let event = if daemon.paused {
+            events.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            events.recv_timeout(daemon.next_change.saturating_duration_since(Instant::now()))
+        };
+        match event {
+            Err(RecvTimeoutError::Timeout) => match daemon.next() {
+                Ok(lines) if daemon.cli.dry_run => {
+                    for line in lines {
+                        println!("{}", line);
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => daemon.report(&err),
+            },
+            Ok(Event::Reload) => {
+                if let Err(err) = daemon.reload() {
+                    daemon.report(&err);
+                }
+            }
+            Ok(Event::Request(request, reply)) => {
+                let response = daemon.handle(request).map_err(|err| {
+                    daemon.report(&err);
+                    Failure::from(err)
+                });
+                // Before replying, so D-Bus clients reading the properties see the change.
+                daemon.publish();
+                if reply.send(response).is_err() {
+                    info!("Client left before the reply was sent");
+                }
+            }
+            Ok(Event::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+        daemon.publish();
+    }
+
+    info!("Shutting down");
+    if let Err(err) = fs::remove_file(&socket_path) {
+        error!("Failed to remove {}: {}", socket_path.display(), err);
+    }
+}
+
+impl Daemon<'_> {
+    /// Lets D-Bus clients know about what changed.
+    fn publish(&self) {
+        if let Some(dbus) = &self.dbus {
+            dbus.update(Snapshot::new(
+                &self.state,
+                self.paused,
+                self.config.interval,
+            ));
+        }
+    }
+
+    fn report(&self, err: &Error) {
+        crate::report_error(err, &self.config.notifications);
+    }
+
+    /// Schedules the next change after the interval, or when the next period of the schedule or
+    /// the next frame of the dynamic set shown starts if that comes first.
+    fn reset_timer(&mut self) {
+        self.next_change = Instant::now() + self.config.interval.min(config::MAX_DURATION);
+        self.follow_schedule();
+    }
+
+    fn follow_schedule(&mut self) {
+        let now = state::now();
+        if let Some(boundary) = self
+            .config
+            .schedule
+            .as_ref()
+            .and_then(|schedule| schedule.next_boundary(now))
+        {
+            if self.change_at(now, boundary) {
+                info!(
+                    "Changing the wallpaper when the next period starts at {}",
+                    schedule::format_local_time(boundary)
+                );
+            }
+        }
+
+        self.refresh_libraries();
+        let Some(libraries) = &self.libraries else {
+            return;
+        };
+        let next_frame = random_wallpaper::shown_dynamic_sets(libraries, &self.state)
+            .filter_map(|dynamic_set| Some((dynamic_set.next_change(now)?, dynamic_set)))
+            .min_by_key(|(next_change, _)| *next_change);
+        if let Some((next_change, dynamic_set)) = next_frame {
+            let message = format!(
+                "Changing the wallpaper when the next frame of {} starts at {}",
+                dynamic_set.path.display(),
+                schedule::format_local_time(next_change)
+            );
+            if self.change_at(now, next_change) {
+                info!("{}", message);
+            }
+        }
+    }
+
+    /// Moves the next change to `timestamp` if that comes first, returning whether it did.
+    fn change_at(&mut self, now: i64, timestamp: i64) -> bool {
+        // A second late, so what starts then has surely started.
+        let at = Instant::now() + Duration::from_secs((timestamp - now + 1).max(0) as u64);
+        if at < self.next_change {
+            self.next_change = at;
+            return true;
+        }
+        false
+    }
+
+    /// Scans the folders again when the last scan is too old.
+    fn refresh_libraries(&mut self) {
+        let stale = match &self.libraries {
+            Some(libraries) => libraries.scanned_at.elapsed() >= RESCAN_INTERVAL,
+            None => true,
+        };
+        if stale {
+            self.libraries = Some(sources::scan_libraries(&self.config, self.backend.as_ref()));
+        }
+    }
+
+    /// Changes the wallpaper and restarts the timer, even when the change failed.
+    fn next(&mut self) -> Result<Vec<String>, Error> {
+        self.refresh_libraries();
+        let result = match &self.libraries {
+            Some(libraries) => random_wallpaper::next_wallpaper(
+                &self.config,
+                self.backend.as_ref(),
+                &mut self.state,
+                libraries,
+                self.cli.dry_run,
+            ),
+            None => Ok(Vec::new()),
+        };
+        self.reset_timer();
+        result
+    }
+
+    /// Reads the config and the state again. An invalid config keeps the current one.
+    fn reload(&mut self) -> Result<(), Error> {
+        info!("Reloading the configuration");
+        let config = crate::load_config(self.cli)?;
+        let backend = random_wallpaper::get_wallpaper_backend(&config)?;
+        let interval_changed = config.interval != self.config.interval;
+        self.config = config;
+        self.backend = backend;
+        self.state = random_wallpaper::get_state(&self.config);
+        self.libraries = None;
+        if interval_changed {
+            info!(
+                "Changing the wallpaper every {}",
+                config::format_duration(self.config.interval)
+            );
+            self.reset_timer();
+        } else {
+            self.follow_schedule();
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn handle(&mut self, request: Request) -> Result<Vec<String>, Error> {
+        match request {
+            Request::Next => {
+                let lines = self.next()?;
+                if self.cli.dry_run {
+                    return Ok(lines);
+                }
+            }
+            Request::Previous => {
+                let result = random_wallpaper::previous_wallpaper(
+                    &self.config,
+                    self.backend.as_ref(),
+                    &mut self.state,
+                    self.cli.dry_run,
+                );
+                self.reset_timer();
+                let lines = result?;
+                if self.cli.dry_run {
+                    return Ok(lines);
+                }
+            }
+            Request::Set(path) => {
+                let lines = random_wallpaper::set_wallpaper(
+                    &self.config,
+                    self.backend.as_ref(),
+                    &mut self.state,
+                    &path,
+                    self.cli.dry_run,
+                )?;
+                self.reset_timer();
+                if self.cli.dry_run {
+                    return Ok(lines);
+                }
+            }
+            Request::Current => {}
+            Request::Favorite(path) => random_wallpaper::favorite_wallpaper(
+                &self.config,
+                &mut self.state,
+                path.as_deref(),
+            )?,
+            Request::Unfavorite(path) => random_wallpaper::unfavorite_wallpaper(
+                &self.config,
+                &mut self.state,
+                path.as_deref(),
+            )?,
+            Request::Rate(rating, path) => random_wallpaper::rate_wallpaper(
+                &self.config,
+                &mut self.state,
+                rating,
+                path.as_deref(),
+            )?,
+            Request::Unrate(path) => {
+                random_wallpaper::unrate_wallpaper(&self.config, &mut self.state, path.as_deref())?
+            }
+            Request::Unban(path) => {
+                random_wallpaper::unban_wallpaper(&self.config, &mut self.state, path.as_deref())?
+            }
+            Request::Ban(path) => {
+                self.refresh_libraries();
+                if let Some(libraries) = &self.libraries {
+                    random_wallpaper::ban_wallpaper(
+                        &self.config,
+                        self.backend.as_ref(),
+                        &mut self.state,
+                        libraries,
+                        path.as_deref(),
+                        self.cli.dry_run,
+                    )?;
+                }
+            }
+            Request::Pause => {
+                info!("Paused");
+                self.paused = true;
+            }
+            Request::Resume => {
+                info!("Resumed");
+                self.paused = false;
+                self.reset_timer();
+            }
+            Request::SetInterval(interval) => {
+                info!(
+                    "Changing the wallpaper every {}",
+                    config::format_duration(interval)
+                );
+                self.config.interval = interval;
+                self.reset_timer();
+            }
+            Request::Reload => self.reload()?,
+            Request::Status => return Ok(self.status()),
+        }
+        Ok(random_wallpaper::describe_current(&self.state))
+    }
+
+    fn status(&self) -> Vec<String> {
+        let mut status = vec![
+            if self.paused {
+                "paused".to_string()
+            } else {
+                format!(
+                    "running, next change in {}",
+                    config::format_duration(
+                        self.next_change.saturating_duration_since(Instant::now())
+                    )
+                )
+            },
+            format!("interval {}", config::format_duration(self.config.interval)),
+        ];
+        if let Some(schedule) = self
+            .config
+            .schedule
+            .as_ref()
+            .filter(|schedule| !schedule.periods.is_empty())
+        {
+            let now = state::now();
+            match (schedule.current_period(now), schedule.next_boundary(now)) {
+                (Some(period), Some(boundary)) => status.push(format!(
+                    "period {} until {}",
+                    period,
+                    schedule::format_local_time(boundary)
+                )),
+                (Some(period), None) => status.push(format!("period {}", period)),
+                (None, _) => status.push("no period today".to_string()),
+            }
+        }
+        if let Some(libraries) = &self.libraries {
+            let now = state::now();
+            for dynamic_set in random_wallpaper::shown_dynamic_sets(libraries, &self.state) {
+                status.push(match dynamic_set.next_change(now) {
+                    Some(next_change) => format!(
+                        "dynamic set {} until {}",
+                        dynamic_set.path.display(),
+                        schedule::format_local_time(next_change)
+                    ),
+                    None => format!("dynamic set {}", dynamic_set.path.display()),
+                });
+            }
+        }
+        status.extend(random_wallpaper::describe_current(&self.state));
+        status
+    }
+}
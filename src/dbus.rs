@@ -0,0 +1,366 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::{info, warn};
+use zbus::blocking::Connection;
+use zbus::fdo;
+use zbus::names::InterfaceName;
+use zbus::zvariant::Value;
+use zbus::{dbus_interface, SignalContext};
+
+use crate::config;
+use crate::control::{Event, Request};
+use crate::history::ALL_OUTPUTS;
+use crate::state::State;
+
+pub const SERVICE_NAME: &str = "org.random_wallpaper.Daemon";
+pub const OBJECT_PATH: &str = "/org/random_wallpaper/Daemon";
+
+/// Changing the wallpaper can take a while, e.g. when a backend needs to start first.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What the daemon publishes through the properties of the interface.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    /// Wallpaper shown on each output, or on every output under `ALL_OUTPUTS`.
+    pub wallpapers: BTreeMap<String, PathBuf>,
+    /// The wallpaper applied last, on any output.
+    pub current_wallpaper: Option<PathBuf>,
+    pub paused: bool,
+    pub interval: Duration,
+}
+
+impl Snapshot {
+    pub fn new(state: &State, paused: bool, interval: Duration) -> Self {
+        Snapshot {
+            wallpapers: state
+                .current
+                .iter()
+                .map(|(output, wallpaper)| (output.clone(), wallpaper.path.clone()))
+                .collect(),
+            current_wallpaper: state
+                .current
+                .values()
+                .max_by_key(|wallpaper| wallpaper.applied_at)
+                .map(|wallpaper| wallpaper.path.clone()),
+            paused,
+            interval,
+        }
+    }
+
+    fn current_wallpaper(&self) -> String {
+        self.current_wallpaper
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// The `org.random_wallpaper.Daemon` interface. Calls are forwarded to the daemon loop like the
+/// requests of the control socket, and the properties are read from the last published snapshot
+/// so the loop never waits for the object server.
+struct DaemonInterface {
+    sender: Mutex<Sender<Event>>,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl DaemonInterface {
+    /// Sends `request` to the daemon loop and waits for it to be handled.
+    fn request(&self, request: Request) -> fdo::Result<()> {
+        let (reply_sender, reply) = mpsc::channel();
+        self.send(Event::Request(request, reply_sender))?;
+        reply
+            .recv_timeout(RESPONSE_TIMEOUT)
+            .map_err(|_| fdo::Error::Failed("The daemon did not answer in time.".to_string()))?
+            .map(|_| ())
+            .map_err(|failure| fdo::Error::Failed(failure.message))
+    }
+
+    fn send(&self, event: Event) -> fdo::Result<()> {
+        self.sender
+            .lock()
+            .map_err(|_| fdo::Error::Failed("The daemon is shutting down.".to_string()))?
+            .send(event)
+            .map_err(|_| fdo::Error::Failed("The daemon is shutting down.".to_string()))
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.snapshot
+            .lock()
+            .map(|snapshot| snapshot.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[dbus_interface(name = "org.random_wallpaper.Daemon")]
+impl DaemonInterface {
+    /// Changes to a random wallpaper.
+    fn next(&self) -> fdo::Result<()> {
+        self.request(Request::Next)
+    }
+
+    /// Goes back to the wallpaper shown before the current one.
+    fn previous(&self) -> fdo::Result<()> {
+        self.request(Request::Previous)
+    }
+
+    /// Changes to the given wallpaper.
+    fn set(&self, path: &str) -> fdo::Result<()> {
+        self.request(Request::Set(PathBuf::from(path)))
+    }
+
+    /// Adds the current wallpaper to the favourites.
+    fn favorite(&self) -> fdo::Result<()> {
+        self.request(Request::Favorite(None))
+    }
+
+    /// Path of the wallpaper applied last, empty before the first change.
+    #[dbus_interface(property)]
+    fn current_wallpaper(&self) -> String {
+        self.snapshot().current_wallpaper()
+    }
+
+    #[dbus_interface(property)]
+    fn paused(&self) -> bool {
+        self.snapshot().paused
+    }
+
+    #[dbus_interface(property)]
+    fn set_paused(&mut self, paused: bool) -> fdo::Result<()> {
+        self.request(if paused {
+            Request::Pause
+        } else {
+            Request::Resume
+        })
+    }
+
+    /// Time between changes, in seconds.
+    #[dbus_interface(property)]
+    fn interval(&self) -> u64 {
+        self.snapshot().interval.as_secs()
+    }
+
+    #[dbus_interface(property)]
+    fn set_interval(&mut self, seconds: u64) -> fdo::Result<()> {
+        if seconds == 0 {
+            return Err(fdo::Error::InvalidArgs(
+                "The interval must be longer than zero.".to_string(),
+            ));
+        }
+        if seconds > config::MAX_DURATION.as_secs() {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "The interval can't be longer than {} seconds.",
+                config::MAX_DURATION.as_secs()
+            )));
+        }
+        self.request(Request::SetInterval(Duration::from_secs(seconds)))
+    }
+
+    /// Emitted for each output whose wallpaper changed. `output` is empty when the wallpaper was
+    /// applied to every output.
+    #[dbus_interface(signal)]
+    async fn wallpaper_changed(
+        ctxt: &SignalContext<'_>,
+        output: &str,
+        path: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// The daemon published on a bus.
+pub struct Service {
+    connection: Connection,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+/// Serves the daemon interface on `connection` and takes `SERVICE_NAME` on it. Requests are sent
+/// to the daemon loop through `sender`.
+///
+/// The daemon passes the session bus, any other connection works as well, e.g. one to a private
+/// `dbus-daemon` built with `zbus::blocking::ConnectionBuilder::address`.
+#[tracing::instrument(skip_all)]
+pub fn serve(
+    connection: Connection,
+    sender: Sender<Event>,
+    snapshot: Snapshot,
+) -> zbus::Result<Service> {
+    let snapshot = Arc::new(Mutex::new(snapshot));
+    let interface = DaemonInterface {
+        sender: Mutex::new(sender),
+        snapshot: snapshot.clone(),
+    };
+    connection.object_server().at(OBJECT_PATH, interface)?;
+    connection.request_name(SERVICE_NAME)?;
+    info!("Serving {} on D-Bus", SERVICE_NAME);
+    Ok(Service {
+        connection,
+        snapshot,
+    })
+}
+
+impl Service {
+    /// Publishes `snapshot`, emitting `WallpaperChanged` for every output showing a new
+    /// wallpaper and `PropertiesChanged` for the properties that changed.
+    #[tracing::instrument(skip(self))]
+    pub fn update(&self, snapshot: Snapshot) {
+        let previous = match self.snapshot.lock() {
+            Ok(mut published) => std::mem::replace(&mut *published, snapshot.clone()),
+            Err(_) => return,
+        };
+        if previous == snapshot {
+            return;
+        }
+        if let Err(err) = self.emit_changes(&previous, &snapshot) {
+            warn!("Failed to emit D-Bus signals: {}", err);
+        }
+    }
+
+    fn emit_changes(&self, previous: &Snapshot, snapshot: &Snapshot) -> zbus::Result<()> {
+        let ctxt = SignalContext::new(self.connection.inner(), OBJECT_PATH)?;
+
+        for (output, path) in &snapshot.wallpapers {
+            if previous.wallpapers.get(output) == Some(path) {
+                continue;
+            }
+            let output = if output == ALL_OUTPUTS { "" } else { output };
+            zbus::block_on(DaemonInterface::wallpaper_changed(
+                &ctxt,
+                output,
+                &path.display().to_string(),
+            ))?;
+        }
+
+        let current_wallpaper = Value::from(snapshot.current_wallpaper());
+        let paused = Value::from(snapshot.paused);
+        let interval = Value::from(snapshot.interval.as_secs());
+        let mut changed = HashMap::new();
+        if previous.current_wallpaper != snapshot.current_wallpaper {
+            changed.insert("CurrentWallpaper", &current_wallpaper);
+        }
+        if previous.paused != snapshot.paused {
+            changed.insert("Paused", &paused);
+        }
+        if previous.interval != snapshot.interval {
+            changed.insert("Interval", &interval);
+        }
+        if !changed.is_empty() {
+            zbus::block_on(fdo::Properties::properties_changed(
+                &ctxt,
+                InterfaceName::from_static_str_unchecked(SERVICE_NAME),
+                &changed,
+                &[],
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::thread;
+
+    use zbus::blocking::{ConnectionBuilder, Proxy, ProxyBuilder};
+    use zbus::CacheProperties;
+
+    use super::*;
+
+    /// A private session bus, stopped when dropped.
+    struct Bus {
+        process: Child,
+        address: String,
+    }
+
+    impl Bus {
+        /// `None` when `dbus-daemon` isn't installed.
+        fn start() -> Option<Bus> {
+            let mut process = match Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+            {
+                Ok(process) => process,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+                Err(err) => panic!("Failed to start dbus-daemon: {}", err),
+            };
+            let mut address = String::new();
+            BufReader::new(process.stdout.as_mut().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Some(Bus {
+                process,
+                address: address.trim().to_string(),
+            })
+        }
+
+        fn connect(&self) -> Connection {
+            ConnectionBuilder::address(self.address.as_str())
+                .unwrap()
+                .build()
+                .unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+        }
+    }
+
+    #[test]
+    fn next_changes_the_published_wallpaper() {
+        let Some(bus) = Bus::start() else {
+            eprintln!("Skipped, dbus-daemon is not installed");
+            return;
+        };
+        let (sender, events) = mpsc::channel();
+        let service = serve(bus.connect(), sender, Snapshot::default()).unwrap();
+
+        let client = bus.connect();
+        let proxy: Proxy = ProxyBuilder::new_bare(&client)
+            .destination(SERVICE_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .interface(SERVICE_NAME)
+            .unwrap()
+            .cache_properties(CacheProperties::No)
+            .build()
+            .unwrap();
+        let mut wallpaper_changed = proxy.receive_signal("WallpaperChanged").unwrap();
+        let call = thread::spawn({
+            let proxy = proxy.clone();
+            move || proxy.call_method("Next", &()).map(|_| ())
+        });
+
+        let Ok(Event::Request(request, reply)) = events.recv_timeout(RESPONSE_TIMEOUT) else {
+            panic!("Expected a request");
+        };
+        assert_eq!(request, Request::Next);
+        let path = PathBuf::from("/wallpapers/forest.jpg");
+        service.update(Snapshot {
+            wallpapers: BTreeMap::from([("DP-1".to_string(), path.clone())]),
+            current_wallpaper: Some(path),
+            paused: false,
+            interval: Duration::from_secs(1800),
+        });
+        reply.send(Ok(Vec::new())).unwrap();
+        call.join().unwrap().unwrap();
+
+        let signal = wallpaper_changed.next().unwrap();
+        assert_eq!(
+            signal.body::<(String, String)>().unwrap(),
+            ("DP-1".to_string(), "/wallpapers/forest.jpg".to_string())
+        );
+        assert_eq!(
+            proxy.get_property::<String>("CurrentWallpaper").unwrap(),
+            "/wallpapers/forest.jpg"
+        );
+        assert!(!proxy.get_property::<bool>("Paused").unwrap());
+        assert_eq!(proxy.get_property::<u64>("Interval").unwrap(), 1800);
+    }
+}
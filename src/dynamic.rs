@@ -0,0 +1,533 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml_edit::Item;
+
+use crate::config::{self, ConfigError, ConfigFile};
+use crate::schedule::{self, Location, StartTime};
+
+/// Extension of the manifests describing a dynamic set.
+pub const MANIFEST_EXTENSION: &str = "toml";
+
+/// Extension of GNOME background slideshows.
+pub const SLIDESHOW_EXTENSION: &str = "xml";
+
+/// Whether the source at `path` is a dynamic set rather than a folder.
+pub fn is_dynamic_set(path: &Path) -> bool {
+    path.is_file()
+        && path.extension().is_some_and(|extension| {
+            extension == MANIFEST_EXTENSION || extension == SLIDESHOW_EXTENSION
+        })
+}
+
+/// One of the wallpapers of a dynamic set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub path: PathBuf,
+    /// How long the previous frame fades into this one, zero for the usual transition.
+    pub fade: Duration,
+}
+
+/// When each frame of a dynamic set is shown.
+#[derive(Debug, Clone)]
+pub enum Timing {
+    /// Each frame starts at a time of day, as the periods of the schedule do.
+    Daily {
+        starts: Vec<StartTime>,
+        location: Option<Location>,
+    },
+    /// Each frame lasts a number of seconds, the frames looping from the Unix time `start`.
+    Cycle { start: i64, lengths: Vec<i64> },
+}
+
+/// Wallpapers shown one after the other according to the time, such as a numbered sequence of
+/// the same landscape throughout the day.
+#[derive(Debug, Clone)]
+pub struct DynamicSet {
+    /// The manifest or the slideshow describing the set.
+    pub path: PathBuf,
+    pub frames: Vec<Frame>,
+    pub timing: Timing,
+}
+
+impl DynamicSet {
+    /// Reads the manifest or the GNOME slideshow at `path`. Sunrise and sunset are computed at
+    /// `location`.
+    #[tracing::instrument]
+    pub fn load(path: &Path, location: Option<Location>) -> Result<DynamicSet, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|err| ConfigError::Read {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == SLIDESHOW_EXTENSION)
+        {
+            parse_slideshow(path, &content)
+        } else {
+            parse_manifest(path, &content, location)
+        }
+    }
+
+    /// The frame shown at `timestamp`, `None` when no frame starts around it, e.g. with only sun
+    /// relative frames during a polar night.
+    pub fn current_frame(&self, timestamp: i64) -> Option<&Frame> {
+        self.position(timestamp)
+            .map(|(index, _)| &self.frames[index])
+    }
+
+    /// When the frame shown at `timestamp` is followed by one showing another file.
+    pub fn next_change(&self, timestamp: i64) -> Option<i64> {
+        let (index, mut next_change) = self.position(timestamp)?;
+        for _ in 0..self.frames.len() {
+            let (next_index, after) = self.position(next_change?)?;
+            if self.frames[next_index].path != self.frames[index].path {
+                break;
+            }
+            next_change = after;
+        }
+        next_change
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.frames.iter().any(|frame| frame.path == path)
+    }
+
+    /// Index of the frame shown at `timestamp`, and when the next one starts.
+    fn position(&self, timestamp: i64) -> Option<(usize, Option<i64>)> {
+        match &self.timing {
+            Timing::Daily { starts, location } => {
+                let starts = schedule::starts_around(*location, starts.clone(), timestamp)
+                    .collect::<Vec<_>>();
+                let (_, index) = starts
+                    .iter()
+                    .filter(|(start, _)| *start <= timestamp)
+                    .max_by_key(|(start, _)| *start)?;
+                let next_change = starts
+                    .iter()
+                    .map(|(start, _)| *start)
+                    .filter(|start| *start > timestamp)
+                    .min();
+                Some((*index, next_change))
+            }
+            Timing::Cycle { start, lengths } => {
+                let elapsed = (timestamp - start).rem_euclid(lengths.iter().sum());
+                let mut end = 0;
+                for (index, length) in lengths.iter().enumerate() {
+                    end += length;
+                    if elapsed < end {
+                        return Some((index, Some(timestamp + end - elapsed)));
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Reads the manifest of a dynamic set: an optional `fade` and either `frames = ["file", ...]`
+/// spread evenly over the day from midnight, or `[[frames]]` tables with a `path` and a `start`.
+/// Paths are relative to the manifest.
+fn parse_manifest(
+    path: &Path,
+    content: &str,
+    location: Option<Location>,
+) -> Result<DynamicSet, ConfigError> {
+    let document = config::parse_document(path, content)?;
+    let file = ConfigFile { path, content };
+    let root = file.table(document.as_table(), "", 0);
+    root.check_keys(&["fade", "frames"])?;
+    let fade = root.duration("fade")?.unwrap_or_default();
+
+    let (paths, starts): (Vec<PathBuf>, Vec<StartTime>) = match document.get("frames") {
+        Some(Item::ArrayOfTables(tables)) => tables
+            .iter()
+            .enumerate()
+            .map(|(index, table)| {
+                let frame = file.table(table, "frames", index);
+                frame.check_keys(&["path", "start"])?;
+                let path = frame
+                    .path("path")?
+                    .ok_or_else(|| frame.invalid("path", "Missing path for frame.".to_string()))?;
+                let start: StartTime = frame.parsed("start")?.ok_or_else(|| {
+                    frame.invalid("start", "Missing start for frame.".to_string())
+                })?;
+                if location.is_none() && start.uses_sun() {
+                    return Err(frame.invalid(
+                        "start",
+                        "Sunrise and sunset need the latitude and longitude of [schedule] in the \
+                         config file."
+                            .to_string(),
+                    ));
+                }
+                Ok((path, start))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .unzip(),
+        _ => {
+            let paths = root.list::<String>("frames")?.unwrap_or_default();
+            let length = 86400 / paths.len().max(1) as u32;
+            paths
+                .into_iter()
+                .enumerate()
+                .map(|(index, path)| {
+                    (
+                        config::expand_path(&path),
+                        StartTime::Fixed(index as u32 * length),
+                    )
+                })
+                .unzip()
+        }
+    };
+    if paths.is_empty() {
+        return Err(root.invalid("frames", "Expected frames.".to_string()));
+    }
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    Ok(DynamicSet {
+        path: path.to_path_buf(),
+        frames: paths
+            .into_iter()
+            .map(|frame_path| Frame {
+                path: directory.join(frame_path),
+                fade,
+            })
+            .collect(),
+        timing: Timing::Daily { starts, location },
+    })
+}
+
+/// Reads a GNOME background slideshow: a `<starttime>`, then `<static>` frames shown for a
+/// `<duration>` in seconds and `<transition>`s fading `<from>` one `<to>` the next.
+fn parse_slideshow(path: &Path, content: &str) -> Result<DynamicSet, ConfigError> {
+    let invalid = |offset: usize, message: String| {
+        let (line, column) = config::position(content, offset);
+        ConfigError::Invalid {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message,
+        }
+    };
+    let content = strip_comments(content);
+    let (background_offset, background) = element(&content, "background", 0)
+        .ok_or_else(|| invalid(0, "Expected a <background> slideshow.".to_string()))?;
+
+    let start = match element(background, "starttime", 0) {
+        Some((offset, starttime)) => {
+            let field = |name: &str| {
+                element(starttime, name, 0)
+                    .and_then(|(_, value)| value.trim().parse::<i64>().ok())
+                    .ok_or_else(|| {
+                        invalid(
+                            background_offset + offset,
+                            format!("Expected a number for <{}> in <starttime>.", name),
+                        )
+                    })
+            };
+            let days = days_from_civil(field("year")?, field("month")?, field("day")?);
+            schedule::local_to_utc(
+                days * 86400 + field("hour")? * 3600 + field("minute")? * 60 + field("second")?,
+            )
+        }
+        None => 0,
+    };
+
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut lengths: Vec<i64> = Vec::new();
+    // A transition starts showing its `to` frame, which the following static one then extends.
+    let mut after_transition = false;
+    for (offset, name, item) in children(background) {
+        let offset = background_offset + offset;
+        let duration = element(item, "duration", 0)
+            .and_then(|(_, duration)| duration.trim().parse::<f64>().ok())
+            .filter(|duration| duration.is_finite() && *duration >= 0.0)
+            .ok_or_else(|| invalid(offset, format!("Expected a <duration> in <{}>.", name)))?;
+        let length = duration.round() as i64;
+        let file = |child: &str| {
+            element(item, child, 0)
+                .map(|(_, file)| {
+                    // Files offered in several sizes list them in <size> elements.
+                    let file = element(file, "size", 0).map_or(file, |(_, size)| size);
+                    PathBuf::from(decode_entities(file.trim()))
+                })
+                .ok_or_else(|| invalid(offset, format!("Expected a <{}> in <{}>.", child, name)))
+        };
+        if name == "static" {
+            let file = file("file")?;
+            match (frames.last(), lengths.last_mut()) {
+                (Some(frame), Some(last_length)) if after_transition && frame.path == file => {
+                    *last_length += length;
+                }
+                _ => {
+                    frames.push(Frame {
+                        path: file,
+                        fade: Duration::ZERO,
+                    });
+                    lengths.push(length);
+                }
+            }
+            after_transition = false;
+        } else {
+            frames.push(Frame {
+                path: file("to")?,
+                fade: Duration::from_secs(length as u64),
+            });
+            lengths.push(length);
+            after_transition = true;
+        }
+    }
+    if lengths.iter().sum::<i64>() <= 0 {
+        return Err(invalid(
+            background_offset,
+            "Expected <static> frames lasting some time.".to_string(),
+        ));
+    }
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    for frame in &mut frames {
+        frame.path = directory.join(&frame.path);
+    }
+    Ok(DynamicSet {
+        path: path.to_path_buf(),
+        frames,
+        timing: Timing::Cycle { start, lengths },
+    })
+}
+
+/// Blanks out `<!-- -->` comments, keeping the offsets of everything else.
+fn strip_comments(content: &str) -> String {
+    let mut content = content.to_string();
+    while let Some(start) = content.find("<!--") {
+        let end = content[start..]
+            .find("-->")
+            .map_or(content.len(), |end| start + end + 3);
+        content.replace_range(start..end, &" ".repeat(end - start));
+    }
+    content
+}
+
+/// Content of the first `<name>` element of `xml` from `from` on, whatever its attributes, with
+/// the offset it starts at.
+fn element<'a>(xml: &'a str, name: &str, from: usize) -> Option<(usize, &'a str)> {
+    let open = format!("<{}", name);
+    let mut search = from;
+    loop {
+        let start = search + xml[search..].find(&open)?;
+        let after = start + open.len();
+        search = after;
+        if !xml[after..].starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            continue;
+        }
+        let tag_end = after + xml[after..].find('>')?;
+        if xml[..tag_end].ends_with('/') {
+            return Some((tag_end + 1, ""));
+        }
+        let close = format!("</{}>", name);
+        let content_end = tag_end + 1 + xml[tag_end + 1..].find(&close)?;
+        return Some((tag_end + 1, &xml[tag_end + 1..content_end]));
+    }
+}
+
+/// The `<static>` and `<transition>` elements of a slideshow, in order.
+fn children(background: &str) -> Vec<(usize, &'static str, &str)> {
+    let mut children = Vec::new();
+    let mut from = 0;
+    loop {
+        let next = ["static", "transition"]
+            .into_iter()
+            .filter_map(|name| Some((element(background, name, from)?, name)))
+            .min_by_key(|((offset, _), _)| *offset);
+        let Some(((offset, content), name)) = next else {
+            return children;
+        };
+        children.push((offset, name, content));
+        from = offset + 1;
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Days since the Unix epoch of a civil date, after Howard Hinnant's `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01 as days since the Unix epoch.
+    const NEW_YEAR: i64 = 19723;
+
+    fn frame(path: &str, fade: u64) -> Frame {
+        Frame {
+            path: PathBuf::from(path),
+            fade: Duration::from_secs(fade),
+        }
+    }
+
+    fn cycle(paths: &[&str], start: i64, lengths: Vec<i64>) -> DynamicSet {
+        DynamicSet {
+            path: PathBuf::from("/sets/cycle.xml"),
+            frames: paths.iter().map(|path| frame(path, 0)).collect(),
+            timing: Timing::Cycle { start, lengths },
+        }
+    }
+
+    #[test]
+    fn counts_days_from_civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 1, 1), NEW_YEAR);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+
+    #[test]
+    fn reads_slideshows() {
+        let content = r#"<?xml version="1.0"?>
+<!-- A <static> in a comment is skipped -->
+<background>
+  <starttime>
+    <year>2024</year><month>1</month><day>1</day>
+    <hour>8</hour><minute>0</minute><second>0</second>
+  </starttime>
+  <static>
+    <duration>3600.0</duration>
+    <file><size width="1920" height="1080">day.jpg</size></file>
+  </static>
+  <transition type="overlay">
+    <duration>600</duration>
+    <from>day.jpg</from>
+    <to>night &amp; stars.jpg</to>
+  </transition>
+  <static>
+    <duration>3000</duration>
+    <file>night &amp; stars.jpg</file>
+  </static>
+  <!-- <static><duration>5</duration><file>skipped.jpg</file></static> -->
+  <transition>
+    <duration>60</duration>
+    <from>night &amp; stars.jpg</from>
+    <to>day.jpg</to>
+  </transition>
+</background>
+"#;
+        let set = parse_slideshow(Path::new("/sets/day.xml"), content).unwrap();
+        assert_eq!(
+            set.frames,
+            [
+                frame("/sets/day.jpg", 0),
+                frame("/sets/night & stars.jpg", 600),
+                frame("/sets/day.jpg", 60),
+            ]
+        );
+        let Timing::Cycle { start, lengths } = set.timing else {
+            panic!("Expected a cycle");
+        };
+        assert_eq!(start, schedule::local_to_utc(NEW_YEAR * 86400 + 8 * 3600));
+        // The static frame after a transition extends it.
+        assert_eq!(lengths, [3600, 3600, 60]);
+    }
+
+    #[test]
+    fn reports_where_a_slideshow_is_invalid() {
+        let err = parse_slideshow(Path::new("a.xml"), "<images/>").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a.xml:1:1: Expected a <background> slideshow."
+        );
+
+        let content =
+            "<background>\n  <static>\n    <file>a.jpg</file>\n  </static>\n</background>";
+        let err = parse_slideshow(Path::new("a.xml"), content).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a.xml:2:11: Expected a <duration> in <static>."
+        );
+
+        let content = "<background><static><duration>0</duration><file>a.jpg</file></static>\
+                       </background>";
+        assert!(parse_slideshow(Path::new("a.xml"), content).is_err());
+    }
+
+    #[test]
+    fn spreads_manifest_frames_over_the_day() {
+        let content = "fade = \"5m\"\nframes = [\"a.jpg\", \"b.jpg\", \"c.jpg\", \"d.jpg\"]\n";
+        let set = parse_manifest(Path::new("/sets/lake.toml"), content, None).unwrap();
+        assert_eq!(
+            set.frames,
+            ["a", "b", "c", "d"].map(|name| frame(&format!("/sets/{}.jpg", name), 300))
+        );
+        let Timing::Daily { starts, location } = &set.timing else {
+            panic!("Expected daily starts");
+        };
+        assert_eq!(
+            starts,
+            &[0, 6, 12, 18].map(|hour| StartTime::Fixed(hour * 3600))
+        );
+        assert_eq!(*location, None);
+
+        let morning = schedule::local_to_utc(NEW_YEAR * 86400 + 7 * 3600);
+        assert_eq!(set.current_frame(morning), Some(&set.frames[1]));
+        assert_eq!(
+            set.next_change(morning),
+            Some(schedule::local_to_utc(NEW_YEAR * 86400 + 12 * 3600))
+        );
+    }
+
+    #[test]
+    fn checks_manifests() {
+        let invalid = |content: &str| {
+            parse_manifest(Path::new("set.toml"), content, None)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(invalid("frames = []"), "set.toml:1:10: Expected frames.");
+        assert_eq!(invalid("frame = []"), "set.toml:1:9: Unknown key frame.");
+        assert_eq!(
+            invalid("[[frames]]\npath = \"a.jpg\"\nstart = \"sunrise\"\n"),
+            "set.toml:3:9: Sunrise and sunset need the latitude and longitude of [schedule] in \
+             the config file."
+        );
+    }
+
+    #[test]
+    fn cycles_wrap_around() {
+        let set = cycle(&["a.jpg", "b.jpg"], 1000, vec![10, 20]);
+        let shown = |timestamp| {
+            let frame = set.current_frame(timestamp).unwrap();
+            (frame.path.to_str().unwrap(), set.next_change(timestamp))
+        };
+        assert_eq!(shown(1000), ("a.jpg", Some(1010)));
+        assert_eq!(shown(1015), ("b.jpg", Some(1030)));
+        assert_eq!(shown(1030), ("a.jpg", Some(1040)));
+        assert_eq!(shown(1000 + 30 * 1000 + 29), ("b.jpg", Some(31030)));
+        // Before the start the cycle runs backwards.
+        assert_eq!(shown(995), ("b.jpg", Some(1000)));
+    }
+
+    #[test]
+    fn next_change_skips_repeated_frames() {
+        let set = cycle(&["a.jpg", "a.jpg", "b.jpg"], 0, vec![10, 10, 10]);
+        assert_eq!(set.next_change(0), Some(20));
+        assert_eq!(set.next_change(15), Some(20));
+        assert_eq!(set.next_change(25), Some(30));
+        assert!(set.contains(Path::new("b.jpg")));
+        assert!(!set.contains(Path::new("c.jpg")));
+    }
+}
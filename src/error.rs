@@ -0,0 +1,105 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::config::ConfigError;
+
+/// Why changing the wallpaper failed. Each kind exits with its own code so scripts can tell them
+/// apart, `1` and `2` being left for other failures and usage errors.
+#[derive(Debug)]
+pub enum Error {
+    Config(ConfigError),
+    /// Nothing to pick from in any of the folders.
+    NoImages {
+        folders: Vec<PathBuf>,
+    },
+    /// The backend could not be started.
+    BackendSpawn {
+        program: String,
+        err: io::Error,
+    },
+    /// The backend ran but could not change the wallpaper.
+    BackendFailed {
+        program: String,
+        message: String,
+    },
+    /// A daemon the backend talks to, e.g. swww-daemon, is not running.
+    BackendNotRunning {
+        program: String,
+        message: String,
+    },
+    State {
+        path: PathBuf,
+        err: io::Error,
+    },
+    /// A wallpaper asked for explicitly that can't be shown.
+    InvalidWallpaper {
+        path: PathBuf,
+        reason: String,
+    },
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 3,
+            Error::NoImages { .. } => 4,
+            Error::BackendSpawn { .. } => 5,
+            Error::BackendFailed { .. } => 6,
+            Error::State { .. } => 7,
+            Error::InvalidWallpaper { .. } => 8,
+            Error::BackendNotRunning { .. } => 9,
+        }
+    }
+
+    /// Icon of the notification reporting the error.
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Error::NoImages { .. } => "dialog-warning",
+            _ => "dialog-error",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(err) => write!(f, "{}", err),
+            Error::NoImages { folders } => write!(
+                f,
+                "No images found in {}",
+                folders
+                    .iter()
+                    .map(|folder| folder.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Error::BackendSpawn { program, err } => write!(f, "Failed to run {}: {}", program, err),
+            Error::BackendFailed { program, message } => {
+                write!(f, "{} failed to change the wallpaper: {}", program, message)
+            }
+            Error::BackendNotRunning { program, message } => {
+                write!(f, "{} is not running: {}", program, message)
+            }
+            Error::State { path, err } => {
+                write!(
+                    f,
+                    "Failed to update the state in {}: {}",
+                    path.display(),
+                    err
+                )
+            }
+            Error::InvalidWallpaper { path, reason } => {
+                write!(f, "Can't use {}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
@@ -0,0 +1,43 @@
+use crate::images::ImageSize;
+
+/// Rejects wallpapers that would look stretched or blurry on the output they are set on.
+#[derive(Debug, Clone, Default)]
+pub struct SizeFilter {
+    pub min_resolution: Option<ImageSize>,
+    /// Largest allowed relative difference between the image and output aspect ratios.
+    pub aspect_ratio_tolerance: Option<f64>,
+    pub match_orientation: bool,
+}
+
+impl SizeFilter {
+    pub fn is_active(&self) -> bool {
+        self.min_resolution.is_some() || self.needs_output_size()
+    }
+
+    pub fn needs_output_size(&self) -> bool {
+        self.aspect_ratio_tolerance.is_some() || self.match_orientation
+    }
+
+    /// Output dependent checks pass when `output_size` is unknown.
+    pub fn accepts(&self, size: ImageSize, output_size: Option<ImageSize>) -> bool {
+        if let Some(min_resolution) = self.min_resolution {
+            if size.width < min_resolution.width || size.height < min_resolution.height {
+                return false;
+            }
+        }
+
+        let Some(output_size) = output_size else {
+            return true;
+        };
+        if self.match_orientation && size.is_portrait() != output_size.is_portrait() {
+            return false;
+        }
+        if let Some(tolerance) = self.aspect_ratio_tolerance {
+            let difference = (size.aspect_ratio() - output_size.aspect_ratio()).abs();
+            if difference / output_size.aspect_ratio() > tolerance {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+/// Output name recorded for a wallpaper applied to every output at once.
+pub const ALL_OUTPUTS: &str = "*";
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub output: String,
+    pub path: PathBuf,
+    /// Unix timestamp, unknown for entries migrated from the legacy cache.
+    pub applied_at: Option<i64>,
+}
+
+/// Wallpapers applied so far, oldest first.
+#[derive(Debug, Default)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Parses the legacy cache made of `output<TAB>path` lines. A line without an output is
+    /// what the first versions wrote and applies to every output.
+    pub fn parse_legacy(content: &str) -> Self {
+        let entries = content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (output, path) = line.split_once('\t').unwrap_or((ALL_OUTPUTS, line));
+                HistoryEntry {
+                    output: output.to_string(),
+                    path: PathBuf::from(path),
+                    applied_at: None,
+                }
+            })
+            .collect();
+        History { entries }
+    }
+
+    /// The last `count` distinct wallpapers shown on `output`, most recent first. Wallpapers
+    /// applied to every output count for each of them, and every entry counts for `ALL_OUTPUTS`.
+    pub fn recent(&self, output: &str, count: usize) -> Vec<&PathBuf> {
+        let mut recent: Vec<&PathBuf> = Vec::new();
+        for entry in self.entries.iter().rev() {
+            if recent.len() >= count {
+                break;
+            }
+            let applies =
+                output == ALL_OUTPUTS || entry.output == output || entry.output == ALL_OUTPUTS;
+            if applies && !recent.contains(&&entry.path) {
+                recent.push(&entry.path);
+            }
+        }
+        recent
+    }
+
+    pub fn push(&mut self, output: &str, path: PathBuf, applied_at: i64) {
+        self.entries.push(HistoryEntry {
+            output: output.to_string(),
+            path,
+            applied_at: Some(applied_at),
+        });
+    }
+
+    /// Drops the oldest entries beyond `limit`.
+    pub fn truncate(&mut self, limit: usize) {
+        let skipped = self.entries.len().saturating_sub(limit);
+        self.entries.drain(..skipped);
+    }
+}
+
+/// Formats a Unix timestamp as an ISO 8601 UTC date and time.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+
+    // Civil date from days since the epoch, after Howard Hinnant's `civil_from_days`.
+    let shifted_days = days + 719468;
+    let era = shifted_days.div_euclid(146097);
+    let day_of_era = shifted_days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
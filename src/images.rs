@@ -0,0 +1,542 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::Path;
+use std::str::FromStr;
+
+const SIGNATURE_LENGTH: usize = 32;
+const AVIF_SEARCH_LENGTH: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Gif,
+    Bmp,
+    Webp,
+    Avif,
+    JpegXl,
+    Tiff,
+    Qoi,
+}
+
+pub const ALL_FORMATS: [ImageFormat; 9] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::Gif,
+    ImageFormat::Bmp,
+    ImageFormat::Webp,
+    ImageFormat::Avif,
+    ImageFormat::JpegXl,
+    ImageFormat::Tiff,
+    ImageFormat::Qoi,
+];
+
+impl ImageFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Png => "png",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Avif => "avif",
+            ImageFormat::JpegXl => "jxl",
+            ImageFormat::Tiff => "tiff",
+            ImageFormat::Qoi => "qoi",
+        }
+    }
+
+    /// Recognises a format from the first bytes of a file.
+    fn from_signature(header: &[u8]) -> Option<Self> {
+        let starts_with = |signature: &[u8]| header.starts_with(signature);
+        let at = |offset: usize, signature: &[u8]| {
+            header.get(offset..offset + signature.len()) == Some(signature)
+        };
+
+        if starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::Jpeg)
+        } else if starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if starts_with(b"GIF87a") || starts_with(b"GIF89a") {
+            Some(ImageFormat::Gif)
+        } else if starts_with(b"BM") && at(6, &[0, 0, 0, 0]) {
+            Some(ImageFormat::Bmp)
+        } else if starts_with(b"RIFF") && at(8, b"WEBP") {
+            Some(ImageFormat::Webp)
+        } else if at(4, b"ftypavif") || at(4, b"ftypavis") {
+            Some(ImageFormat::Avif)
+        } else if starts_with(&[0xFF, 0x0A]) || starts_with(b"\0\0\0\x0CJXL \r\n\x87\n") {
+            Some(ImageFormat::JpegXl)
+        } else if starts_with(b"II*\0") || starts_with(b"MM\0*") {
+            Some(ImageFormat::Tiff)
+        } else if starts_with(b"qoif") {
+            Some(ImageFormat::Qoi)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "jpg" => Ok(ImageFormat::Jpeg),
+            "tif" => Ok(ImageFormat::Tiff),
+            "jpegxl" | "jpeg-xl" => Ok(ImageFormat::JpegXl),
+            name => ALL_FORMATS
+                .into_iter()
+                .find(|format| format.name() == name)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown image format {}, expected one of {}.",
+                        value,
+                        ALL_FORMATS.map(|format| format.name()).join(", ")
+                    )
+                }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ImageSize {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+
+    pub fn is_portrait(&self) -> bool {
+        self.height > self.width
+    }
+}
+
+impl fmt::Display for ImageSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// Parses sizes written as `1920x1080`.
+impl FromStr for ImageSize {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid size {}, expected WIDTHxHEIGHT.", value);
+        let (width, height) = value.trim().split_once('x').ok_or_else(invalid)?;
+        Ok(ImageSize {
+            width: width.trim().parse().map_err(|_| invalid())?,
+            height: height.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Detects the format of an image from its content, regardless of its extension. Files that are
+/// not a known image format yield `None`.
+#[tracing::instrument]
+pub fn detect_image_format(path: &Path) -> io::Result<Option<ImageFormat>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(ImageFormat::from_signature(&read_signature(&mut reader)?))
+}
+
+fn read_signature(reader: &mut BufReader<File>) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(SIGNATURE_LENGTH);
+    reader
+        .by_ref()
+        .take(SIGNATURE_LENGTH as u64)
+        .read_to_end(&mut header)?;
+    reader.rewind()?;
+    Ok(header)
+}
+
+/// Reads the dimensions of an image from its header without decoding the pixel data.
+#[tracing::instrument]
+pub fn read_image_size(path: &Path) -> io::Result<ImageSize> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let format = ImageFormat::from_signature(&read_signature(&mut reader)?)
+        .ok_or_else(|| invalid_header("unknown image format"))?;
+
+    let size = match format {
+        ImageFormat::Png => read_png_size(&mut reader)?,
+        ImageFormat::Jpeg => read_jpeg_size(&mut reader)?,
+        ImageFormat::Gif => read_gif_size(&mut reader)?,
+        ImageFormat::Bmp => read_bmp_size(&mut reader)?,
+        ImageFormat::Webp => read_webp_size(&mut reader)?,
+        ImageFormat::Avif => read_avif_size(&mut reader)?,
+        ImageFormat::Tiff => read_tiff_size(&mut reader)?,
+        ImageFormat::Qoi => read_qoi_size(&mut reader)?,
+        ImageFormat::JpegXl => {
+            return Err(invalid_header(
+                "reading the size of JPEG XL images is not supported",
+            ))
+        }
+    };
+
+    if size.width == 0 || size.height == 0 {
+        return Err(invalid_header("image has no pixels"));
+    }
+    Ok(size)
+}
+
+fn invalid_header(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn skip(reader: &mut BufReader<File>, count: i64) -> io::Result<()> {
+    reader.seek_relative(count)
+}
+
+/// The IHDR chunk always comes first and starts with the big-endian width and height.
+fn read_png_size(reader: &mut BufReader<File>) -> io::Result<ImageSize> {
+    let header = read_bytes::<24>(reader)?;
+    if &header[12..16] != b"IHDR" {
+        return Err(invalid_header("missing PNG IHDR chunk"));
+    }
+    Ok(ImageSize {
+        width: u32::from_be_bytes([header[16], header[17], header[18], header[19]]),
+        height: u32::from_be_bytes([header[20], header[21], header[22], header[23]]),
+    })
+}
+
+/// Walks the marker segments until the first start-of-frame, which holds the dimensions.
+fn read_jpeg_size(reader: &mut BufReader<File>) -> io::Result<ImageSize> {
+    skip(reader, 2)?;
+    loop {
+        let [prefix, mut marker] = read_bytes::<2>(reader)?;
+        if prefix != 0xFF {
+            return Err(invalid_header("corrupt JPEG marker"));
+        }
+        while marker == 0xFF {
+            [marker] = read_bytes::<1>(reader)?;
+        }
+
+        match marker {
+            0x01 | 0xD0..=0xD7 => continue,
+            0xD9 | 0xDA => return Err(invalid_header("JPEG has no frame header")),
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes(read_bytes::<2>(reader)?) as i64;
+        let is_start_of_frame =
+            matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if is_start_of_frame {
+            let frame = read_bytes::<5>(reader)?;
+            return Ok(ImageSize {
+                width: u16::from_be_bytes([frame[3], frame[4]]) as u32,
+                height: u16::from_be_bytes([frame[1], frame[2]]) as u32,
+            });
+        }
+        skip(reader, length - 2)?;
+    }
+}
+
+fn read_gif_size(reader: &mut BufReader<File>) -> io::Result<ImageSize> {
+    let header = read_bytes::<10>(reader)?;
+    Ok(ImageSize {
+        width: u16::from_le_bytes([header[6], header[7]]) as u32,
+        height: u16::from_le_bytes([header[8], header[9]]) as u32,
+    })
+}
+
+/// Old OS/2 bitmaps use 16-bit dimensions, every later DIB header 32-bit ones where a negative
+/// height marks a top-down image.
+fn read_bmp_size(reader: &mut BufReader<File>) -> io::Result<ImageSize> {
+    skip(reader, 14)?;
+    let dib_header_size = u32::from_le_bytes(read_bytes::<4>(reader)?);
+    if dib_header_size == 12 {
+        let dimensions = read_bytes::<4>(reader)?;
+        return Ok(ImageSize {
+            width: u16::from_le_bytes([dimensions[0], dimensions[1]]) as u32,
+            height: u16::from_le_bytes([dimensions[2], dimensions[3]]) as u32,
+        });
+    }
+
+    let dimensions = read_bytes::<8>(reader)?;
+    let width = i32::from_le_bytes([dimensions[0], dimensions[1], dimensions[2], dimensions[3]]);
+    let height = i32::from_le_bytes([dimensions[4], dimensions[5], dimensions[6], dimensions[7]]);
+    Ok(ImageSize {
+        width: width.unsigned_abs(),
+        height: height.unsigned_abs(),
+    })
+}
+
+/// The first chunk decides the layout: lossy `VP8 `, lossless `VP8L` or extended `VP8X`.
+fn read_webp_size(reader: &mut BufReader<File>) -> io::Result<ImageSize> {
+    let header = read_bytes::<30>(reader)?;
+    let chunk = &header[20..];
+    match &header[12..16] {
+        b"VP8 " => Ok(ImageSize {
+            width: (u16::from_le_bytes([chunk[6], chunk[7]]) & 0x3FFF) as u32,
+            height: (u16::from_le_bytes([chunk[8], chunk[9]]) & 0x3FFF) as u32,
+        }),
+        b"VP8L" => {
+            let bits = u32::from_le_bytes([chunk[1], chunk[2], chunk[3], chunk[4]]);
+            Ok(ImageSize {
+                width: (bits & 0x3FFF) + 1,
+                height: ((bits >> 14) & 0x3FFF) + 1,
+            })
+        }
+        b"VP8X" => Ok(ImageSize {
+            width: u32::from_le_bytes([chunk[4], chunk[5], chunk[6], 0]) + 1,
+            height: u32::from_le_bytes([chunk[7], chunk[8], chunk[9], 0]) + 1,
+        }),
+        _ => Err(invalid_header("unknown WebP chunk")),
+    }
+}
+
+/// Looks for the `ispe` property holding the image spatial extents, which sits in the metadata
+/// boxes near the start of the file.
+fn read_avif_size(reader: &mut BufReader<File>) -> io::Result<ImageSize> {
+    let mut header = Vec::new();
+    reader
+        .by_ref()
+        .take(AVIF_SEARCH_LENGTH)
+        .read_to_end(&mut header)?;
+    let position = header
+        .windows(4)
+        .position(|window| window == b"ispe")
+        .ok_or_else(|| invalid_header("missing AVIF ispe property"))?;
+    let extents = header
+        .get(position + 8..position + 16)
+        .ok_or_else(|| invalid_header("truncated AVIF ispe property"))?;
+    Ok(ImageSize {
+        width: u32::from_be_bytes([extents[0], extents[1], extents[2], extents[3]]),
+        height: u32::from_be_bytes([extents[4], extents[5], extents[6], extents[7]]),
+    })
+}
+
+/// Reads the width and height tags of the first image file directory.
+fn read_tiff_size(reader: &mut BufReader<File>) -> io::Result<ImageSize> {
+    let header = read_bytes::<8>(reader)?;
+    let little_endian = header[0] == b'I';
+    let to_u16 = |bytes: [u8; 2]| {
+        if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    };
+    let to_u32 = |bytes: [u8; 4]| {
+        if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+
+    let directory_offset = to_u32([header[4], header[5], header[6], header[7]]);
+    skip(reader, directory_offset as i64 - 8)?;
+    let entry_count = to_u16(read_bytes::<2>(reader)?);
+
+    let (mut width, mut height) = (None, None);
+    for _ in 0..entry_count {
+        let entry = read_bytes::<12>(reader)?;
+        let value = match to_u16([entry[2], entry[3]]) {
+            3 => to_u16([entry[8], entry[9]]) as u32,
+            4 => to_u32([entry[8], entry[9], entry[10], entry[11]]),
+            _ => continue,
+        };
+        match to_u16([entry[0], entry[1]]) {
+            256 => width = Some(value),
+            257 => height = Some(value),
+            _ => {}
+        }
+    }
+
+    match (width, height) {
+        (Some(width), Some(height)) => Ok(ImageSize { width, height }),
+        _ => Err(invalid_header("missing TIFF dimensions")),
+    }
+}
+
+fn read_qoi_size(reader: &mut BufReader<File>) -> io::Result<ImageSize> {
+    let header = read_bytes::<12>(reader)?;
+    Ok(ImageSize {
+        width: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+        height: u32::from_be_bytes([header[8], header[9], header[10], header[11]]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A file holding `bytes` in the temporary directory, removed when dropped.
+    struct TestImage(PathBuf);
+
+    impl TestImage {
+        fn new(name: &str, bytes: &[u8]) -> TestImage {
+            let path = std::env::temp_dir().join(format!(
+                "random-wallpaper-{}-{}",
+                std::process::id(),
+                name
+            ));
+            fs::write(&path, bytes).unwrap();
+            TestImage(path)
+        }
+
+        fn size(&self) -> io::Result<ImageSize> {
+            read_image_size(&self.0)
+        }
+    }
+
+    impl Drop for TestImage {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        bytes.extend(width.to_be_bytes());
+        bytes.extend(height.to_be_bytes());
+        bytes.extend([8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        bytes
+    }
+
+    /// A JFIF segment, then a baseline start-of-frame.
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10];
+        bytes.extend(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        bytes.extend([0xFF, 0xC0, 0x00, 0x11, 0x08]);
+        bytes.extend(height.to_be_bytes());
+        bytes.extend(width.to_be_bytes());
+        bytes.extend([0x03, 0x01, 0x22, 0x00]);
+        bytes
+    }
+
+    fn webp(chunk: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WEBP".to_vec();
+        bytes.extend(chunk);
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn reads_png_size() {
+        let image = TestImage::new("size.png", &png(1920, 1080));
+        assert_eq!(
+            detect_image_format(&image.0).unwrap(),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            image.size().unwrap(),
+            ImageSize {
+                width: 1920,
+                height: 1080
+            }
+        );
+    }
+
+    #[test]
+    fn reads_jpeg_size() {
+        let image = TestImage::new("size.jpg", &jpeg(3840, 2160));
+        assert_eq!(
+            detect_image_format(&image.0).unwrap(),
+            Some(ImageFormat::Jpeg)
+        );
+        assert_eq!(
+            image.size().unwrap(),
+            ImageSize {
+                width: 3840,
+                height: 2160
+            }
+        );
+    }
+
+    #[test]
+    fn reads_webp_sizes() {
+        let lossy = TestImage::new(
+            "lossy.webp",
+            &webp(
+                b"VP8 ",
+                &[0, 0, 0, 0x9D, 0x01, 0x2A, 0x80, 0x07, 0x38, 0x04],
+            ),
+        );
+        let bits: u32 = (1600 - 1) | ((900 - 1) << 14);
+        let mut lossless_data = vec![0x2F];
+        lossless_data.extend(bits.to_le_bytes());
+        lossless_data.extend([0; 5]);
+        let lossless = TestImage::new("lossless.webp", &webp(b"VP8L", &lossless_data));
+        let extended = TestImage::new(
+            "extended.webp",
+            &webp(b"VP8X", &[0x10, 0, 0, 0, 0xFF, 0x09, 0, 0x3F, 0x06, 0]),
+        );
+
+        assert_eq!(
+            detect_image_format(&lossy.0).unwrap(),
+            Some(ImageFormat::Webp)
+        );
+        assert_eq!(
+            lossy.size().unwrap(),
+            ImageSize {
+                width: 1920,
+                height: 1080
+            }
+        );
+        assert_eq!(
+            lossless.size().unwrap(),
+            ImageSize {
+                width: 1600,
+                height: 900
+            }
+        );
+        assert_eq!(
+            extended.size().unwrap(),
+            ImageSize {
+                width: 2560,
+                height: 1600
+            }
+        );
+    }
+
+    #[test]
+    fn fails_on_truncated_headers() {
+        let png = png(1920, 1080);
+        let jpeg = jpeg(3840, 2160);
+        let webp = webp(
+            b"VP8 ",
+            &[0, 0, 0, 0x9D, 0x01, 0x2A, 0x80, 0x07, 0x38, 0x04],
+        );
+        for (name, bytes) in [
+            ("truncated.png", &png[..20]),
+            ("truncated.jpg", &jpeg[..22]),
+            ("truncated.webp", &webp[..24]),
+        ] {
+            let image = TestImage::new(name, bytes);
+            let err = image.size().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let text = TestImage::new("text.png", b"not an image at all");
+        assert_eq!(detect_image_format(&text.0).unwrap(), None);
+        assert_eq!(text.size().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let empty = TestImage::new("empty.png", &png(0, 1080));
+        assert_eq!(empty.size().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,696 @@
+//! Picks random wallpapers from folders and applies them through a Wayland wallpaper backend.
+//!
+//! The functions at the root run one operation each, the way the `random-wallpaper` command
+//! does: they load or update the [`state::State`], apply wallpapers through a
+//! [`backends::WallpaperBackend`] and notify the result. The modules hold the pieces they are
+//! built from, such as scanning in [`sources`] and picking in [`selection`], for tools that only
+//! need part of it.
+//!
+//! Desktop notifications are behind the `notifications` feature. Without it new wallpapers are
+//! only logged.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tracing::{error, info, warn};
+
+use config::{Config, ConfigError};
+use dynamic::DynamicSet;
+use error::Error;
+use filters::SizeFilter;
+use history::ALL_OUTPUTS;
+use images::ImageSize;
+use marks::{MarkSet, Rating};
+use notify::send_wallpaper_changed_notification;
+use schedule::Period;
+use selection::{
+    choose_wallpaper, filter_by_size, get_possible_wallpapers, SelectionMode, Weights,
+};
+use sources::{get_allowed_formats, is_image, Libraries, SourceWallpapers};
+use state::State;
+
+pub mod backends;
+pub mod config;
+pub mod control;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+pub mod dynamic;
+pub mod error;
+pub mod filters;
+pub mod history;
+pub mod images;
+pub mod marks;
+pub mod notify;
+pub mod outputs;
+pub mod schedule;
+pub mod selection;
+pub mod sources;
+pub mod state;
+pub mod template;
+
+/// History entries kept in the state, raised when the history window needs more.
+pub const HISTORY_LIMIT: usize = 500;
+
+/// Where a wallpaper is applied: a single output, or every output when `output` is `None`.
+#[derive(Debug)]
+struct Target {
+    output: Option<String>,
+    size: Option<ImageSize>,
+}
+
+#[tracing::instrument(skip(config))]
+pub fn get_state(config: &Config) -> State {
+    let state = State::load(&config.state_file, &config.cache_file);
+    for (output, wallpaper) in &state.current {
+        info!(
+            "Previously used wallpaper on {}: {}",
+            output,
+            wallpaper.path.display()
+        )
+    }
+    state
+}
+
+/// The backend defaults to the one named after the command, so pointing `RW_WALLPAPER_CHANGER` at
+/// e.g. `swaybg` keeps working without also setting `RW_WALLPAPER_BACKEND`.
+#[tracing::instrument(skip(config))]
+pub fn get_wallpaper_backend(
+    config: &Config,
+) -> Result<Box<dyn backends::WallpaperBackend>, Error> {
+    let command_name = config
+        .command
+        .as_deref()
+        .and_then(|command| Path::new(command).file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .filter(|name| backends::BACKEND_NAMES.contains(&name.as_str()));
+    let name = config
+        .backend
+        .as_deref()
+        .or(command_name.as_deref())
+        .unwrap_or("swww");
+
+    backends::from_name(
+        name,
+        config.command.clone(),
+        config.transition.clone(),
+        config.swww_daemon.clone(),
+        config.fit.clone(),
+        config.custom_resident,
+    )
+    .map_err(|message| Error::Config(ConfigError::Backend(message)))
+}
+
+/// With `per_output` set every output gets its own wallpaper, otherwise a single target covers
+/// them all. That target is sized after the first output when the size filter needs one.
+#[tracing::instrument]
+fn get_targets(
+    backend: &dyn backends::WallpaperBackend,
+    per_output: bool,
+    size_filter: &SizeFilter,
+) -> Vec<Target> {
+    let mut per_output = per_output;
+    if per_output && !backend.supports_outputs() {
+        warn!("{} cannot set wallpapers per output", backend.name());
+        per_output = false;
+    }
+    if !per_output && !size_filter.needs_output_size() {
+        return vec![Target {
+            output: None,
+            size: None,
+        }];
+    }
+
+    let outputs = backend.outputs();
+    if per_output && !outputs.is_empty() {
+        return outputs
+            .into_iter()
+            .map(|output| Target {
+                size: Some(output.size()),
+                output: Some(output.name),
+            })
+            .collect();
+    }
+    if per_output {
+        warn!("No outputs found, using the same wallpaper everywhere");
+    }
+    vec![Target {
+        output: None,
+        size: outputs.first().map(outputs::Output::size),
+    }]
+}
+
+#[tracing::instrument(skip(config))]
+pub fn apply_new_wallpaper(
+    backend: &dyn backends::WallpaperBackend,
+    output: Option<&str>,
+    selected_file: &PathBuf,
+    fade: Option<Duration>,
+    config: &Config,
+) -> Result<(), Error> {
+    match fade {
+        Some(duration) => backend.fade(output, selected_file, duration)?,
+        None => backend.apply(output, selected_file)?,
+    }
+    let source = config
+        .sources
+        .iter()
+        .find(|source| selected_file.starts_with(&source.path));
+    let wallpaper = notify::Wallpaper {
+        output,
+        path: selected_file,
+        source: source.map(|source| source.path.as_path()),
+    };
+    send_wallpaper_changed_notification(&wallpaper, &config.notifications);
+    info!(
+        "Wallpaper successfully changed to {} on {}",
+        selected_file.display(),
+        output.unwrap_or(ALL_OUTPUTS)
+    );
+    Ok(())
+}
+
+#[tracing::instrument(skip(state))]
+pub fn update_cache(
+    state_file_path: &Path,
+    state: &mut State,
+    history_limit: usize,
+) -> Result<(), Error> {
+    state.history.truncate(history_limit);
+    state.save(state_file_path).map_err(|err| Error::State {
+        path: state_file_path.to_path_buf(),
+        err,
+    })
+}
+
+pub fn format_wallpaper(output: &str, selected_file: &Path) -> String {
+    if output == ALL_OUTPUTS {
+        selected_file.display().to_string()
+    } else {
+        format!("{}: {}", output, selected_file.display())
+    }
+}
+
+/// The current wallpaper of each output, one per line.
+pub fn describe_current(state: &State) -> Vec<String> {
+    state
+        .current
+        .iter()
+        .map(|(output, wallpaper)| format_wallpaper(output, &wallpaper.path))
+        .collect()
+}
+
+/// The period of the schedule at `timestamp`, if there is a schedule.
+pub fn current_period(config: &Config, timestamp: i64) -> Option<&Period> {
+    config
+        .schedule
+        .as_ref()
+        .and_then(|schedule| schedule.current_period(timestamp))
+}
+
+/// The libraries to pick from during `period`, or from the configured sources without one or when
+/// the period matches none of the libraries.
+pub fn active_libraries<'a>(
+    config: &Config,
+    libraries: &'a [SourceWallpapers],
+    period: Option<&Period>,
+) -> Vec<&'a SourceWallpapers> {
+    if let Some(period) = period {
+        let period_libraries = libraries
+            .iter()
+            .filter(|library| period.includes(&library.source))
+            .collect::<Vec<_>>();
+        if !period_libraries.is_empty() {
+            return period_libraries;
+        }
+        warn!(
+            "The {} period matches no source, picking from the configured sources",
+            period
+        );
+    }
+    libraries
+        .iter()
+        .filter(|library| {
+            config
+                .sources
+                .iter()
+                .any(|source| source.path == library.source.path)
+        })
+        .collect()
+}
+
+/// The frame of `dynamic_set` to show now, unless it is banned or can't be displayed. Shown on
+/// every output, whatever was shown recently.
+fn get_current_frame(
+    library: &SourceWallpapers,
+    dynamic_set: &DynamicSet,
+    banned: &MarkSet,
+) -> Vec<PathBuf> {
+    dynamic_set
+        .current_frame(state::now())
+        .map(|frame| &frame.path)
+        .filter(|path| library.wallpapers.contains(path) && !banned.contains(path))
+        .cloned()
+        .into_iter()
+        .collect()
+}
+
+/// Whether `output` already shows `selected_file` as a frame of a dynamic set, which stays the
+/// current frame until the next one starts.
+fn shows_frame(
+    libraries: &[&SourceWallpapers],
+    state: &State,
+    output: &str,
+    selected_file: &Path,
+) -> bool {
+    state
+        .current
+        .get(output)
+        .is_some_and(|wallpaper| wallpaper.path == selected_file)
+        && libraries
+            .iter()
+            .filter_map(|library| library.dynamic_set.as_ref())
+            .any(|dynamic_set| dynamic_set.contains(selected_file))
+}
+
+/// How long to cross-fade into `selected_file` on `output`, when it is the current frame of a
+/// dynamic set following another frame of the same set.
+fn get_fade(
+    libraries: &[&SourceWallpapers],
+    state: &State,
+    output: &str,
+    selected_file: &Path,
+) -> Option<Duration> {
+    let now = state::now();
+    let (dynamic_set, frame) = libraries
+        .iter()
+        .filter_map(|library| library.dynamic_set.as_ref())
+        .find_map(|dynamic_set| {
+            let frame = dynamic_set.current_frame(now)?;
+            (frame.path == selected_file).then_some((dynamic_set, frame))
+        })?;
+    let previous_file = &state.current.get(output)?.path;
+    if previous_file == selected_file || !dynamic_set.contains(previous_file) {
+        return None;
+    }
+    (!frame.fade.is_zero()).then_some(frame.fade)
+}
+
+/// The dynamic sets a frame of which is currently shown.
+pub fn shown_dynamic_sets<'a>(
+    libraries: &'a Libraries,
+    state: &'a State,
+) -> impl Iterator<Item = &'a DynamicSet> {
+    libraries
+        .libraries
+        .iter()
+        .filter_map(|library| library.dynamic_set.as_ref())
+        .filter(|dynamic_set| {
+            state
+                .current
+                .values()
+                .any(|wallpaper| dynamic_set.contains(&wallpaper.path))
+        })
+}
+
+/// Picks and applies a new wallpaper for every target, or only picks them with `dry_run`, and
+/// returns them as [`format_wallpaper`] lines. Fails with the first target that could not be
+/// changed, after trying the others.
+#[tracing::instrument(skip(config, state, libraries))]
+pub fn next_wallpaper(
+    config: &Config,
+    backend: &dyn backends::WallpaperBackend,
+    state: &mut State,
+    libraries: &Libraries,
+    dry_run: bool,
+) -> Result<Vec<String>, Error> {
+    let history_size = config.history_size;
+    let size_filter = &config.size_filter;
+    let Libraries {
+        libraries: all_libraries,
+        wallpaper_sizes,
+        ..
+    } = libraries;
+    let period = current_period(config, state::now());
+    if let Some(period) = period {
+        info!("Picking from the {} period", period);
+    }
+    let libraries = active_libraries(config, all_libraries, period);
+
+    if libraries
+        .iter()
+        .all(|library| library.wallpapers.is_empty())
+    {
+        return Err(Error::NoImages {
+            folders: libraries
+                .iter()
+                .map(|library| library.source.path.clone())
+                .collect(),
+        });
+    }
+
+    let all_wallpapers = || all_libraries.iter().flat_map(|library| &library.wallpapers);
+    state.favorites.relocate(all_wallpapers());
+    state.banned.relocate(all_wallpapers());
+    state.ratings.relocate(all_wallpapers());
+
+    let selection_mode = config.selection_mode;
+    state.selection_mode = Some(selection_mode);
+    if selection_mode == SelectionMode::Shuffle {
+        state.shuffle_bag.reconcile(all_wallpapers());
+    }
+
+    let targets = get_targets(backend, config.per_output, size_filter);
+    let history_limit = HISTORY_LIMIT.max(history_size * targets.len());
+    let mut selected_files: Vec<PathBuf> = Vec::new();
+    let mut lines = Vec::new();
+    let mut failure = None;
+    for target in targets {
+        let output = target.output;
+        let key = output.as_deref().unwrap_or(ALL_OUTPUTS);
+        let recent_wallpapers = state.history.recent(key, history_size);
+
+        // Shrink the no-repeat window until something is left to pick from.
+        let mut possible_wallpapers = Vec::new();
+        for window in (0..=recent_wallpapers.len()).rev() {
+            let mut excluded_wallpapers = selected_files.iter().collect::<Vec<_>>();
+            excluded_wallpapers.extend(&recent_wallpapers[..window]);
+
+            possible_wallpapers = libraries
+                .iter()
+                .map(|library| {
+                    let mut possible_wallpapers = match &library.dynamic_set {
+                        Some(dynamic_set) => get_current_frame(library, dynamic_set, &state.banned),
+                        None => get_possible_wallpapers(
+                            &library.wallpapers,
+                            &excluded_wallpapers,
+                            &state.banned,
+                        ),
+                    };
+                    if size_filter.is_active() {
+                        possible_wallpapers = filter_by_size(
+                            possible_wallpapers,
+                            wallpaper_sizes,
+                            size_filter,
+                            target.size,
+                        );
+                    }
+                    (*library, possible_wallpapers)
+                })
+                .collect::<Vec<_>>();
+            if possible_wallpapers
+                .iter()
+                .any(|(_, wallpapers)| !wallpapers.is_empty())
+            {
+                if window < recent_wallpapers.len() {
+                    info!("Only excluding the last {} wallpapers on {}", window, key);
+                }
+                break;
+            }
+        }
+
+        let shuffle_bag = match selection_mode {
+            SelectionMode::Random => None,
+            SelectionMode::Shuffle => Some(&mut state.shuffle_bag),
+        };
+        let weights = Weights {
+            strategy: config.strategy,
+            favorites: &state.favorites,
+            favorite_weight: config.favorite_weight,
+            ratings: &state.ratings,
+            rating_weight: &config.rating_weight,
+            unrated_weight: config.unrated_weight,
+        };
+        let Some(selected_file) = choose_wallpaper(&possible_wallpapers, shuffle_bag, &weights)
+        else {
+            warn!("No other images left for {}", key);
+            continue;
+        };
+        if dry_run {
+            lines.push(format_wallpaper(key, &selected_file));
+        } else if shows_frame(&libraries, state, key, &selected_file) {
+            info!("Already showing {} on {}", selected_file.display(), key);
+            lines.push(format_wallpaper(key, &selected_file));
+        } else {
+            let fade = get_fade(&libraries, state, key, &selected_file);
+            match apply_new_wallpaper(backend, output.as_deref(), &selected_file, fade, config) {
+                Ok(()) => {
+                    lines.push(format_wallpaper(key, &selected_file));
+                    state.record(key, selected_file.clone());
+                }
+                Err(err) if failure.is_none() => failure = Some(err),
+                Err(err) => error!("{}", err),
+            }
+        }
+        selected_files.push(selected_file);
+    }
+    if !dry_run {
+        update_cache(&config.state_file, state, history_limit)?;
+    }
+    failure.map_or(Ok(lines), Err)
+}
+
+/// Applies `selected_file` to every output, and returns it as a [`format_wallpaper`] line.
+#[tracing::instrument(skip(config, state))]
+pub fn set_wallpaper(
+    config: &Config,
+    backend: &dyn backends::WallpaperBackend,
+    state: &mut State,
+    selected_file: &Path,
+    dry_run: bool,
+) -> Result<Vec<String>, Error> {
+    let allowed_formats = get_allowed_formats(backend, &config.image_formats);
+    let selected_file = selected_file
+        .canonicalize()
+        .map_err(|err| Error::InvalidWallpaper {
+            path: selected_file.to_path_buf(),
+            reason: err.to_string(),
+        })?;
+    if !is_image(&selected_file, &allowed_formats) {
+        return Err(Error::InvalidWallpaper {
+            reason: format!("not an image {} can display", backend.name()),
+            path: selected_file,
+        });
+    }
+
+    let lines = vec![format_wallpaper(ALL_OUTPUTS, &selected_file)];
+    if dry_run {
+        return Ok(lines);
+    }
+    apply_new_wallpaper(backend, None, &selected_file, None, config)?;
+    state.record(ALL_OUTPUTS, selected_file);
+    update_cache(
+        &config.state_file,
+        state,
+        HISTORY_LIMIT.max(config.history_size),
+    )?;
+    Ok(lines)
+}
+
+/// Goes back to the wallpaper shown before the current one on each output, and returns them as
+/// [`format_wallpaper`] lines.
+#[tracing::instrument(skip(config, state))]
+pub fn previous_wallpaper(
+    config: &Config,
+    backend: &dyn backends::WallpaperBackend,
+    state: &mut State,
+    dry_run: bool,
+) -> Result<Vec<String>, Error> {
+    let outputs = state.current.keys().cloned().collect::<Vec<_>>();
+    if outputs.is_empty() {
+        warn!("No wallpaper has been applied yet");
+        return Ok(Vec::new());
+    }
+
+    let mut lines = Vec::new();
+    let mut failure = None;
+    for output in outputs {
+        let Some(previous_file) = state
+            .history
+            .recent(&output, 2)
+            .get(1)
+            .map(|path| (*path).clone())
+        else {
+            warn!("No previous wallpaper on {}", output);
+            continue;
+        };
+        let target_output = (output != ALL_OUTPUTS).then_some(output.as_str());
+        if dry_run {
+            lines.push(format_wallpaper(&output, &previous_file));
+            continue;
+        }
+        match apply_new_wallpaper(backend, target_output, &previous_file, None, config) {
+            Ok(()) => {
+                lines.push(format_wallpaper(&output, &previous_file));
+                state.record(&output, previous_file);
+            }
+            Err(err) if failure.is_none() => failure = Some(err),
+            Err(err) => error!("{}", err),
+        }
+    }
+    if !dry_run {
+        update_cache(
+            &config.state_file,
+            state,
+            HISTORY_LIMIT.max(config.history_size),
+        )?;
+    }
+    failure.map_or(Ok(lines), Err)
+}
+
+/// `path`, or the wallpapers currently shown when none is given.
+fn get_wallpapers_to_mark(state: &State, path: Option<&Path>) -> Vec<PathBuf> {
+    match path {
+        Some(path) => vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())],
+        None => state
+            .current
+            .values()
+            .map(|wallpaper| wallpaper.path.clone())
+            .collect(),
+    }
+}
+
+#[tracing::instrument(skip(config, state))]
+pub fn favorite_wallpaper(
+    config: &Config,
+    state: &mut State,
+    path: Option<&Path>,
+) -> Result<(), Error> {
+    let wallpapers = get_wallpapers_to_mark(state, path);
+    if wallpapers.is_empty() {
+        warn!("No wallpaper has been applied yet");
+        return Ok(());
+    }
+    for wallpaper in wallpapers {
+        info!("Added {} to the favourites", wallpaper.display());
+        state.favorites.insert(wallpaper);
+    }
+    update_cache(
+        &config.state_file,
+        state,
+        HISTORY_LIMIT.max(config.history_size),
+    )
+}
+
+#[tracing::instrument(skip(config, state))]
+pub fn unfavorite_wallpaper(
+    config: &Config,
+    state: &mut State,
+    path: Option<&Path>,
+) -> Result<(), Error> {
+    for wallpaper in get_wallpapers_to_mark(state, path) {
+        if state.favorites.remove(&wallpaper) {
+            info!("Removed {} from the favourites", wallpaper.display());
+        } else {
+            warn!("{} is not a favourite", wallpaper.display());
+        }
+    }
+    update_cache(
+        &config.state_file,
+        state,
+        HISTORY_LIMIT.max(config.history_size),
+    )
+}
+
+#[tracing::instrument(skip(config, state))]
+pub fn unban_wallpaper(
+    config: &Config,
+    state: &mut State,
+    path: Option<&Path>,
+) -> Result<(), Error> {
+    for wallpaper in get_wallpapers_to_mark(state, path) {
+        if state.banned.remove(&wallpaper) {
+            info!("Unbanned {}", wallpaper.display());
+        } else {
+            warn!("{} is not banned", wallpaper.display());
+        }
+    }
+    update_cache(
+        &config.state_file,
+        state,
+        HISTORY_LIMIT.max(config.history_size),
+    )
+}
+
+#[tracing::instrument(skip(config, state))]
+pub fn rate_wallpaper(
+    config: &Config,
+    state: &mut State,
+    rating: Rating,
+    path: Option<&Path>,
+) -> Result<(), Error> {
+    let wallpapers = get_wallpapers_to_mark(state, path);
+    if wallpapers.is_empty() {
+        warn!("No wallpaper has been applied yet");
+        return Ok(());
+    }
+    for wallpaper in wallpapers {
+        info!("Rated {} {} out of 5", wallpaper.display(), rating);
+        state.ratings.set(wallpaper, rating);
+    }
+    update_cache(
+        &config.state_file,
+        state,
+        HISTORY_LIMIT.max(config.history_size),
+    )
+}
+
+#[tracing::instrument(skip(config, state))]
+pub fn unrate_wallpaper(
+    config: &Config,
+    state: &mut State,
+    path: Option<&Path>,
+) -> Result<(), Error> {
+    for wallpaper in get_wallpapers_to_mark(state, path) {
+        if state.ratings.remove(&wallpaper) {
+            info!("Removed the rating of {}", wallpaper.display());
+        } else {
+            warn!("{} is not rated", wallpaper.display());
+        }
+    }
+    update_cache(
+        &config.state_file,
+        state,
+        HISTORY_LIMIT.max(config.history_size),
+    )
+}
+
+/// Bans the wallpapers and replaces them from `libraries` when they are currently shown, or only
+/// logs them with `dry_run`.
+#[tracing::instrument(skip(config, state, libraries))]
+pub fn ban_wallpaper(
+    config: &Config,
+    backend: &dyn backends::WallpaperBackend,
+    state: &mut State,
+    libraries: &Libraries,
+    path: Option<&Path>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let wallpapers = get_wallpapers_to_mark(state, path);
+    if wallpapers.is_empty() {
+        warn!("No wallpaper has been applied yet");
+        return Ok(());
+    }
+    if dry_run {
+        for wallpaper in wallpapers {
+            info!("Would ban {}", wallpaper.display());
+        }
+        return Ok(());
+    }
+    let shown = state
+        .current
+        .values()
+        .any(|wallpaper| wallpapers.contains(&wallpaper.path));
+    for wallpaper in wallpapers {
+        info!("Banned {}", wallpaper.display());
+        state.banned.insert(wallpaper);
+    }
+    update_cache(
+        &config.state_file,
+        state,
+        HISTORY_LIMIT.max(config.history_size),
+    )?;
+    if shown {
+        next_wallpaper(config, backend, state, libraries, false)?;
+    }
+    Ok(())
+}
@@ -1,226 +1,436 @@
 use std::env;
-use std::fs::{self, File};
-use std::io::{BufReader, Read, Write};
-use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus};
-
-use heck::ToShoutySnakeCase;
-use notify_rust::{Hint, Notification};
-use rand_core::OsRng;
-use rand_distr::Distribution;
-use rand_distr::Uniform;
-use tracing::{error, info, warn, Level};
-use tracing_unwrap::{OptionExt, ResultExt};
-
-use EnvVar::{CacheFile, WallpaperChanger, WallpaperFolder};
-
-const APP_NAME: &str = "Random Wallpaper";
-
-const TRANSITION_TYPE: &str = "any";
-const TRANSITION_STEP: &str = "30";
-const TRANSITION_DURATION: &str = "3";
-const TRANSITION_FPS: &str = "165";
-
-const EXPIRE_TIME: i32 = 3000;
-
-#[derive(Debug)]
-enum EnvVar {
-    CacheFile,
-    WallpaperFolder,
-    WallpaperChanger,
-}
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
 
-impl ToString for EnvVar {
-    #[tracing::instrument]
-    fn to_string(&self) -> String {
-        format!("RW_{:?}", self).to_shouty_snake_case()
-    }
-}
+use tracing::{error, Level};
+
+use cli::{Cli, Command};
+use random_wallpaper::config::{self, Config, NotificationConfig};
+use random_wallpaper::control::{self, Request};
+use random_wallpaper::error::Error;
+use random_wallpaper::notify::{self, send_error_notification};
+use random_wallpaper::schedule;
+use random_wallpaper::sources::{scan_libraries, Libraries};
+use random_wallpaper::state::{self, State};
+use random_wallpaper::{
+    active_libraries, backends, ban_wallpaper, current_period, describe_current,
+    favorite_wallpaper, get_state, get_wallpaper_backend, history, next_wallpaper,
+    previous_wallpaper, rate_wallpaper, set_wallpaper, unban_wallpaper, unfavorite_wallpaper,
+    unrate_wallpaper,
+};
+
+mod cli;
+mod daemon;
 
 fn setup_tracing_subscriber() {
     let subscriber = tracing_subscriber::fmt()
         .with_max_level(Level::INFO)
+        .with_writer(std::io::stderr)
         .finish();
     tracing::subscriber::set_global_default(subscriber)
         .expect("Failed to set global tracing subscriber");
 }
 
-#[tracing::instrument]
-fn get_value_from_env_var_or_default(env_var: EnvVar, default: &str) -> String {
-    let env_value_result = env::var(env_var.to_string());
-    if let Ok(env_value) = env_value_result {
-        return env_value;
-    }
-    default.to_string()
+/// Logs `err` and shows it in a notification.
+fn report_error(err: &Error, notification_config: &NotificationConfig) {
+    error!("{}", err);
+    send_error_notification(&err.to_string(), err.icon(), notification_config);
 }
 
-#[tracing::instrument]
-fn get_cache_file_path() -> PathBuf {
-    let path = get_value_from_env_var_or_default(CacheFile, "~/.wallpaper");
-    PathBuf::from(shellexpand::tilde(&path).to_string())
+fn exit_with_error(err: &Error, notification_config: &NotificationConfig) -> ! {
+    report_error(err, notification_config);
+    process::exit(err.exit_code());
 }
 
-#[tracing::instrument]
-fn get_previously_used_wallpaper(cache_file_path: &PathBuf) -> String {
-    let mut previous_wallpaper = String::new();
-    if let Ok(mut file) = File::open(cache_file_path) {
-        BufReader::new(&mut file)
-            .read_to_string(&mut previous_wallpaper)
-            .expect_or_log("Failed to read cache file.");
+#[tracing::instrument(skip(config))]
+fn list_wallpapers(config: &Config, backend: &dyn backends::WallpaperBackend, state: &State) {
+    let libraries = scan_libraries(config, backend);
+    let period = current_period(config, state::now());
+    for wallpaper in active_libraries(config, &libraries.libraries, period)
+        .into_iter()
+        .flat_map(|library| &library.wallpapers)
+    {
+        if !state.banned.contains(wallpaper) {
+            println!("{}", wallpaper.display());
+        }
+    }
+}
 
-        info!("Previously used wallpaper: {}", previous_wallpaper)
+fn print_current(state: &State) {
+    for line in describe_current(state) {
+        println!("{}", line);
     }
-    previous_wallpaper
 }
 
-#[tracing::instrument]
-fn get_wallpaper_directory_path() -> PathBuf {
-    let path = get_value_from_env_var_or_default(WallpaperFolder, "~/Pictures/wallpapers");
-    PathBuf::from(shellexpand::tilde(&path).to_string())
+fn print_history(state: &State) {
+    for entry in &state.history.entries {
+        println!(
+            "{}\t{}\t{}",
+            entry
+                .applied_at
+                .map(history::format_timestamp)
+                .unwrap_or_else(|| "-".to_string()),
+            entry.output,
+            entry.path.display()
+        );
+    }
 }
 
-#[tracing::instrument]
-fn get_possible_wallpapers(
-    previous_wallpaper: String,
-    wallpaper_directory_path: &PathBuf,
-) -> Vec<PathBuf> {
-    fs::read_dir(wallpaper_directory_path)
-        .expect_or_log(format!("Failed to open {}", &wallpaper_directory_path.display()).as_str())
-        .filter_map(|entry| {
-            if let Ok(dir_entry) = entry {
-                let path = dir_entry.path();
-                if path.is_file() {
-                    Some(path)
-                } else {
-                    None
-                }
-            } else {
-                None
+/// Runs the actions clicked on the notifications of a one-shot run, for as long as they are shown.
+/// The folders are only scanned again when the run didn't already.
+#[tracing::instrument(skip_all)]
+fn handle_notification_actions(
+    config: &Config,
+    backend: &dyn backends::WallpaperBackend,
+    state: &mut State,
+    mut libraries: Option<Libraries>,
+    actions: &Receiver<Option<Request>>,
+) {
+    let timeout = Duration::from_millis(config.notifications.timeout.max(0) as u64);
+    while notify::offering_actions() > 0 {
+        let Ok(request) = actions.recv_timeout(timeout) else {
+            break;
+        };
+        let result = match request {
+            Some(Request::Next) => {
+                let libraries = libraries.get_or_insert_with(|| scan_libraries(config, backend));
+                next_wallpaper(config, backend, state, libraries, false).map(|_| ())
+            }
+            Some(Request::Favorite(path)) => favorite_wallpaper(config, state, path.as_deref()),
+            Some(Request::Ban(path)) => {
+                let libraries = libraries.get_or_insert_with(|| scan_libraries(config, backend));
+                ban_wallpaper(config, backend, state, libraries, path.as_deref(), false)
+            }
+            Some(Request::Rate(rating, path)) => {
+                rate_wallpaper(config, state, rating, path.as_deref())
             }
-        })
-        .filter(|file_path| is_image(file_path))
-        .filter(|file_path| file_path != Path::new(&previous_wallpaper))
-        .collect::<Vec<_>>()
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            report_error(&err, &config.notifications);
+        }
+    }
 }
 
+/// Prints a line per check and returns whether all of them passed.
 #[tracing::instrument]
-fn is_image(path: &Path) -> bool {
-    match path.extension() {
-        Some(ext) => {
-            matches!(
-                ext.to_str(),
-                Some("jpg") | Some("jpeg") | Some("png") | Some("gif") | Some("bmp")
-            )
+fn run_doctor(cli: &Cli) -> bool {
+    let mut healthy = true;
+    let mut report = |passed: bool, check: &str, details: &str| {
+        println!(
+            "{} {}: {}",
+            if passed { "ok  " } else { "FAIL" },
+            check,
+            details
+        );
+        healthy &= passed;
+    };
+
+    let config_file_path = cli
+        .config_file
+        .clone()
+        .unwrap_or_else(config::get_config_file_path);
+    let config = match Config::load(cli.config_file.clone()) {
+        Ok(mut config) => {
+            cli.apply(&mut config);
+            if config_file_path.exists() {
+                report(true, "config", &config_file_path.display().to_string());
+            } else {
+                report(
+                    true,
+                    "config",
+                    &format!("{} not found, using defaults", config_file_path.display()),
+                );
+            }
+            config
+        }
+        Err(err) => {
+            report(false, "config", &err.to_string());
+            return false;
+        }
+    };
+
+    match get_wallpaper_backend(&config) {
+        Ok(backend) => {
+            match backend.check() {
+                Ok(details) => report(true, backend.name(), &details),
+                Err(details) => report(false, backend.name(), &details),
+            }
+            let outputs = backend
+                .outputs()
+                .iter()
+                .map(|output| output.to_string())
+                .collect::<Vec<_>>();
+            if outputs.is_empty() {
+                report(!config.per_output, "outputs", "none found");
+            } else {
+                report(true, "outputs", &outputs.join(", "));
+            }
+            let libraries = scan_libraries(&config, backend.as_ref());
+            for library in libraries.libraries {
+                let details = match &library.dynamic_set {
+                    Some(dynamic_set) => format!(
+                        "{}: dynamic set of {} frames, {} found",
+                        library.source,
+                        dynamic_set.frames.len(),
+                        library.wallpapers.len()
+                    ),
+                    None => format!(
+                        "{}: {} wallpapers",
+                        library.source,
+                        library.wallpapers.len()
+                    ),
+                };
+                report(!library.wallpapers.is_empty(), "source", &details);
+            }
         }
-        _ => false,
+        Err(err) => report(false, "backend", &err.to_string()),
     }
-}
 
-#[tracing::instrument]
-fn choose_random_wallpaper(possible_wallpapers: &Vec<PathBuf>) -> &PathBuf {
-    let distribution = Uniform::new(0, possible_wallpapers.len());
-    &possible_wallpapers[distribution.sample(&mut OsRng)]
-}
+    if let Some(schedule) = config
+        .schedule
+        .as_ref()
+        .filter(|schedule| !schedule.periods.is_empty())
+    {
+        let now = state::now();
+        match (schedule.current_period(now), schedule.next_boundary(now)) {
+            (Some(period), Some(boundary)) => report(
+                true,
+                "schedule",
+                &format!(
+                    "{} period until {}",
+                    period,
+                    schedule::format_local_time(boundary)
+                ),
+            ),
+            (Some(period), None) => report(true, "schedule", &format!("{} period", period)),
+            (None, _) => report(
+                true,
+                "schedule",
+                "no period today, using the configured sources",
+            ),
+        }
+    }
 
-#[tracing::instrument]
-fn get_file_name(selected_file: &PathBuf) -> String {
-    selected_file
-        .file_name()
-        .expect_or_log(
-            format!("Failed to get file name from {}.", selected_file.display()).as_str(),
-        )
-        .to_string_lossy()
-        .to_string()
-}
+    if config.state_file.exists() {
+        let state = State::load(&config.state_file, &config.cache_file);
+        report(
+            true,
+            "state",
+            &format!(
+                "{}: {} history entries",
+                config.state_file.display(),
+                state.history.entries.len()
+            ),
+        );
+    } else {
+        report(
+            true,
+            "state",
+            &format!("{} not created yet", config.state_file.display()),
+        );
+    }
 
-#[tracing::instrument]
-fn apply_new_wallpaper(cache_file_path: &PathBuf, selected_file: &PathBuf) {
-    let command = get_value_from_env_var_or_default(WallpaperChanger, "swww");
-    let status = execute_wallpaper_changer(&command, selected_file);
-
-    if status.success() {
-        update_cache(cache_file_path, selected_file);
-        send_wallpaper_changed_notification(selected_file);
-        info!(
-            "Wallpaper successfully changed to {}",
-            selected_file.display()
-        )
+    if config.notifications.enabled {
+        match notify::server_name() {
+            Ok(name) => report(true, "notifications", &name),
+            Err(err) => report(false, "notifications", &err),
+        }
+    } else {
+        report(true, "notifications", "disabled");
     }
+    healthy
 }
 
+/// Config with the command line options applied.
 #[tracing::instrument]
-fn execute_wallpaper_changer(command: &str, selected_file: &PathBuf) -> ExitStatus {
-    Command::new(command)
-        .arg("img")
-        .args(["--transition-type", TRANSITION_TYPE])
-        .args(["--transition-step", TRANSITION_STEP])
-        .args(["--transition-duration", TRANSITION_DURATION])
-        .args(["--transition-fps", TRANSITION_FPS])
-        .arg(selected_file)
-        .status()
-        .expect_or_log(format!("Failed to execute {}.", command).as_str())
+fn load_config(cli: &Cli) -> Result<Config, Error> {
+    let mut config = Config::load(cli.config_file.clone())?;
+    cli.apply(&mut config);
+    Ok(config)
 }
 
-#[tracing::instrument]
-fn update_cache(cache_file_path: &PathBuf, file_path: &PathBuf) {
-    let mut cache_file = File::create(cache_file_path).expect_or_log(
-        format!("Failed to create cache file {}.", cache_file_path.display()).as_str(),
-    );
-
-    cache_file
-        .write_all(file_path.to_string_lossy().as_bytes())
-        .expect_or_log(format!("Failed to update cache in {}", cache_file_path.display()).as_str());
+/// The request to hand to a running daemon instead of acting directly. Dry runs always stay local
+/// and paths are made absolute since the daemon runs elsewhere.
+fn get_daemon_request(cli: &Cli) -> Option<Request> {
+    if cli.dry_run {
+        return None;
+    }
+    let absolute = |path: &PathBuf| path.canonicalize().unwrap_or_else(|_| path.clone());
+    match &cli.command {
+        Command::Next => Some(Request::Next),
+        Command::Previous => Some(Request::Previous),
+        Command::Set(path) => Some(Request::Set(absolute(path))),
+        Command::Current => Some(Request::Current),
+        Command::Favorite(path) => Some(Request::Favorite(path.as_ref().map(absolute))),
+        Command::Unfavorite(path) => Some(Request::Unfavorite(path.as_ref().map(absolute))),
+        Command::Ban(path) => Some(Request::Ban(path.as_ref().map(absolute))),
+        Command::Unban(path) => Some(Request::Unban(path.as_ref().map(absolute))),
+        Command::Rate(rating, path) => Some(Request::Rate(*rating, path.as_ref().map(absolute))),
+        Command::Unrate(path) => Some(Request::Unrate(path.as_ref().map(absolute))),
+        Command::Pause => Some(Request::Pause),
+        Command::Resume => Some(Request::Resume),
+        Command::SetInterval(interval) => Some(Request::SetInterval(*interval)),
+        Command::Reload => Some(Request::Reload),
+        Command::Status => Some(Request::Status),
+        _ => None,
+    }
 }
 
-#[tracing::instrument]
-fn send_notification(body: &str, icon: &str, sticky: bool) {
-    let mut notification_builder: &mut Notification = &mut Notification::new();
-    notification_builder = notification_builder
-        .summary(APP_NAME)
-        .body(body)
-        .icon(icon)
-        .timeout(EXPIRE_TIME);
+fn main() {
+    setup_tracing_subscriber();
 
-    if sticky {
-        notification_builder = notification_builder
-            .timeout(i32::MAX)
-            .hint(Hint::Resident(true));
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    match cli.command {
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Command::Version => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Command::Doctor => {
+            if !run_doctor(&cli) {
+                process::exit(1);
+            }
+            return;
+        }
+        _ => {}
     }
 
-    let result = notification_builder.finalize().show();
-    if result.is_err() {
-        error!("Failed to send notification.");
+    if let Some(request) = get_daemon_request(&cli) {
+        let socket_path = control::get_socket_path();
+        let config_options = cli.config_options();
+        if !config_options.is_empty() && control::is_daemon_running(&socket_path) {
+            eprintln!(
+                "{} can't be applied to the running daemon, change its config and reload it \
+                 instead.",
+                config_options.join(", ")
+            );
+            process::exit(2);
+        }
+        match control::send(&socket_path, &request) {
+            Ok(Ok(lines)) => {
+                for line in lines {
+                    println!("{}", line);
+                }
+                return;
+            }
+            Ok(Err(failure)) => {
+                eprintln!("{}", failure);
+                process::exit(failure.exit_code);
+            }
+            Err(err) if cli.command.needs_daemon() => {
+                eprintln!("No daemon running: {}", err);
+                process::exit(1);
+            }
+            Err(_) => {}
+        }
     }
-}
 
-#[tracing::instrument]
-fn send_wallpaper_changed_notification(selected_file: &PathBuf) {
-    send_notification(
-        get_file_name(selected_file).as_str(),
-        &selected_file.to_string_lossy(),
-        false,
-    )
+    let config = match load_config(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            let notification_config = NotificationConfig {
+                enabled: !cli.no_notifications,
+                ..NotificationConfig::default()
+            };
+            exit_with_error(&err, &notification_config)
+        }
+    };
+    let notification_config = config.notifications.clone();
+    if let Err(err) = run(&cli, config) {
+        exit_with_error(&err, &notification_config);
+    }
 }
 
-fn main() {
-    setup_tracing_subscriber();
-
-    let cache_file_path = get_cache_file_path();
-    let previous_wallpaper = get_previously_used_wallpaper(&cache_file_path);
-    let wallpaper_directory_path = get_wallpaper_directory_path();
-    let possible_wallpapers =
-        get_possible_wallpapers(previous_wallpaper, &wallpaper_directory_path);
-
-    if possible_wallpapers.is_empty() {
-        warn!("No images found in {}", &wallpaper_directory_path.display());
-        send_notification(
-            format!("No images found in {}", &wallpaper_directory_path.display()).as_str(),
-            "dialog-warning",
-            true,
-        );
-        return;
+/// Runs the command locally once the config is loaded.
+fn run(cli: &Cli, config: Config) -> Result<(), Error> {
+    let mut state = get_state(&config);
+    match &cli.command {
+        Command::Current => {
+            print_current(&state);
+            return Ok(());
+        }
+        Command::History => {
+            print_history(&state);
+            return Ok(());
+        }
+        Command::Favorite(path) => return favorite_wallpaper(&config, &mut state, path.as_deref()),
+        Command::Unfavorite(path) => {
+            return unfavorite_wallpaper(&config, &mut state, path.as_deref())
+        }
+        Command::Unban(path) => return unban_wallpaper(&config, &mut state, path.as_deref()),
+        Command::Rate(rating, path) => {
+            return rate_wallpaper(&config, &mut state, *rating, path.as_deref())
+        }
+        Command::Unrate(path) => return unrate_wallpaper(&config, &mut state, path.as_deref()),
+        _ => {}
     }
 
-    let selected_file = choose_random_wallpaper(&possible_wallpapers);
-    apply_new_wallpaper(&cache_file_path, selected_file);
+    let backend = get_wallpaper_backend(&config)?;
+    if cli.command == Command::Daemon {
+        daemon::run(cli, config, backend, state);
+        return Ok(());
+    }
+    let backend = backend.as_ref();
+    // Notifications only offer actions while this process is around to run them.
+    let offer_actions = !cli.dry_run
+        && notify::notifies_wallpapers(&config.notifications)
+        && !config.notifications.actions.is_empty()
+        && config.notifications.timeout > 0;
+    let actions = offer_actions.then(|| {
+        let (sender, actions) = mpsc::channel();
+        // `None` wakes the wait up to check whether a notification still offers actions.
+        notify::set_action_handler(move |action, path| {
+            let _ = sender.send(action.and_then(|action| Request::from_action(action, path)));
+        });
+        actions
+    });
+    let current_wallpapers = state.current.clone();
+    let mut libraries = None;
+    let picked = match &cli.command {
+        Command::Set(path) => set_wallpaper(&config, backend, &mut state, path, cli.dry_run)?,
+        Command::Previous => previous_wallpaper(&config, backend, &mut state, cli.dry_run)?,
+        Command::List => {
+            list_wallpapers(&config, backend, &state);
+            Vec::new()
+        }
+        Command::Ban(path) => {
+            let libraries = libraries.insert(scan_libraries(&config, backend));
+            ban_wallpaper(
+                &config,
+                backend,
+                &mut state,
+                libraries,
+                path.as_deref(),
+                cli.dry_run,
+            )?;
+            Vec::new()
+        }
+        _ => {
+            let libraries = libraries.insert(scan_libraries(&config, backend));
+            next_wallpaper(&config, backend, &mut state, libraries, cli.dry_run)?
+        }
+    };
+    if cli.dry_run {
+        for line in picked {
+            println!("{}", line);
+        }
+    }
+    if let Some(actions) = actions {
+        if state.current != current_wallpapers {
+            handle_notification_actions(&config, backend, &mut state, libraries, &actions);
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use sha1::{Digest, Sha1};
+use tracing::{info, warn};
+
+/// How much the user likes a wallpaper, from 1 to 5 stars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Rating(u8);
+
+impl Rating {
+    pub const MIN: Rating = Rating(1);
+    pub const MAX: Rating = Rating(5);
+    /// What unrated wallpapers count as by default.
+    pub const NEUTRAL: Rating = Rating(3);
+
+    pub fn new(stars: u8) -> Option<Rating> {
+        (Rating::MIN.0..=Rating::MAX.0)
+            .contains(&stars)
+            .then_some(Rating(stars))
+    }
+
+    pub fn stars(self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Rating {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .trim()
+            .parse()
+            .ok()
+            .and_then(Rating::new)
+            .ok_or_else(|| format!("Invalid rating {}, expected 1 to 5.", value))
+    }
+}
+
+/// A wallpaper in the favourites, the banned list or the ratings, with the `value` given to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mark<T = ()> {
+    pub path: PathBuf,
+    /// File size in bytes, to only hash files that can match.
+    pub size: Option<u64>,
+    /// SHA-1 of the content, in hex.
+    pub sha1: Option<String>,
+    pub value: T,
+}
+
+impl<T> Mark<T> {
+    /// Marks `path`, fingerprinting its content when it can be read.
+    pub fn new(path: PathBuf, value: T) -> Self {
+        let (size, sha1) = match fingerprint(&path) {
+            Ok((size, sha1)) => (Some(size), Some(sha1)),
+            Err(err) => {
+                warn!("Failed to hash {}: {}", path.display(), err);
+                (None, None)
+            }
+        };
+        Mark {
+            path,
+            size,
+            sha1,
+            value,
+        }
+    }
+}
+
+/// Wallpapers marked by the user, recognised by their path and, once moved or renamed, by their
+/// content.
+#[derive(Debug, Clone)]
+pub struct MarkSet<T = ()> {
+    marks: BTreeMap<PathBuf, Mark<T>>,
+}
+
+impl<T> Default for MarkSet<T> {
+    fn default() -> Self {
+        MarkSet {
+            marks: BTreeMap::new(),
+        }
+    }
+}
+
+impl MarkSet {
+    /// Adds `path`, returning whether it was not marked yet.
+    pub fn insert(&mut self, path: PathBuf) -> bool {
+        if self.contains(&path) {
+            return false;
+        }
+        self.set(path, ());
+        true
+    }
+}
+
+impl<T> MarkSet<T> {
+    pub fn from_marks(marks: impl IntoIterator<Item = Mark<T>>) -> Self {
+        MarkSet {
+            marks: marks
+                .into_iter()
+                .map(|mark| (mark.path.clone(), mark))
+                .collect(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Mark<T>> {
+        self.marks.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.marks.contains_key(path)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&T> {
+        self.marks.get(path).map(|mark| &mark.value)
+    }
+
+    /// Gives `value` to `path`, marking it when it was not yet.
+    pub fn set(&mut self, path: PathBuf, value: T) {
+        match self.marks.get_mut(&path) {
+            Some(mark) => mark.value = value,
+            None => {
+                self.marks.insert(path.clone(), Mark::new(path, value));
+            }
+        }
+    }
+
+    /// Removes `path`, or the marks with the same content when it was marked under another name.
+    /// Returns whether anything was removed.
+    pub fn remove(&mut self, path: &Path) -> bool {
+        if self.marks.remove(path).is_some() {
+            return true;
+        }
+        let Ok((size, sha1)) = fingerprint(path) else {
+            return false;
+        };
+        let previous_count = self.marks.len();
+        self.marks.retain(|_, mark| {
+            mark.size != Some(size) || mark.sha1.as_deref() != Some(sha1.as_str())
+        });
+        self.marks.len() != previous_count
+    }
+
+    /// Follows marked files that were moved or renamed to one of `wallpapers`, matching them by
+    /// size and content. Marks from older state files get their fingerprint on the way.
+    #[tracing::instrument(skip(self, wallpapers))]
+    pub fn relocate<'a>(&mut self, wallpapers: impl Iterator<Item = &'a PathBuf>) {
+        for mark in self.marks.values_mut() {
+            if mark.sha1.is_none() && mark.path.is_file() {
+                if let Ok((size, sha1)) = fingerprint(&mark.path) {
+                    mark.size = Some(size);
+                    mark.sha1 = Some(sha1);
+                }
+            }
+        }
+
+        let mut missing: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        for mark in self.marks.values() {
+            if let (Some(size), Some(_)) = (mark.size, &mark.sha1) {
+                if !mark.path.exists() {
+                    missing.entry(size).or_default().push(mark.path.clone());
+                }
+            }
+        }
+        if missing.is_empty() {
+            return;
+        }
+
+        for wallpaper in wallpapers {
+            if self.marks.contains_key(wallpaper) {
+                continue;
+            }
+            let Some(candidates) = fs::metadata(wallpaper)
+                .ok()
+                .and_then(|metadata| missing.get_mut(&metadata.len()))
+            else {
+                continue;
+            };
+            let Ok((_, sha1)) = fingerprint(wallpaper) else {
+                continue;
+            };
+            let Some(index) = candidates
+                .iter()
+                .position(|path| self.marks[path].sha1.as_deref() == Some(sha1.as_str()))
+            else {
+                continue;
+            };
+            let previous_path = candidates.swap_remove(index);
+            if let Some(mut mark) = self.marks.remove(&previous_path) {
+                info!(
+                    "{} was moved to {}",
+                    previous_path.display(),
+                    wallpaper.display()
+                );
+                mark.path = wallpaper.clone();
+                self.marks.insert(wallpaper.clone(), mark);
+            }
+        }
+    }
+}
+
+/// Size and SHA-1 of the file at `path`.
+fn fingerprint(path: &Path) -> io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha1::new();
+    let size = io::copy(&mut file, &mut hasher)?;
+    Ok((size, hex::encode(hasher.finalize())))
+}
@@ -0,0 +1,409 @@
+//! Desktop notifications of new wallpapers and errors. Without the `notifications` feature
+//! nothing is shown, and the settings are still parsed so configs stay valid.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+#[cfg(feature = "notifications")]
+use notify_rust::{Hint, Notification};
+#[cfg(feature = "notifications")]
+use tracing::{error, info};
+
+use crate::config::{self, NotificationConfig};
+use crate::images;
+use crate::marks::Rating;
+use crate::state;
+use crate::template;
+
+pub const APP_NAME: &str = "Random Wallpaper";
+
+const PLACEHOLDERS: [&str; 6] = [
+    "filename",
+    "directory",
+    "path",
+    "resolution",
+    "source",
+    "output",
+];
+
+/// Which notifications are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationFilter {
+    All,
+    /// Only new wallpapers.
+    Success,
+    Errors,
+}
+
+impl FromStr for NotificationFilter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "all" => Ok(NotificationFilter::All),
+            "success" => Ok(NotificationFilter::Success),
+            "errors" => Ok(NotificationFilter::Errors),
+            _ => Err(format!(
+                "Unknown notifications {}, expected all, success or errors.",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl FromStr for Urgency {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "low" => Ok(Urgency::Low),
+            "normal" => Ok(Urgency::Normal),
+            "critical" => Ok(Urgency::Critical),
+            _ => Err(format!(
+                "Unknown urgency {}, expected low, normal or critical.",
+                value
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "notifications")]
+impl From<Urgency> for notify_rust::Urgency {
+    fn from(urgency: Urgency) -> Self {
+        match urgency {
+            Urgency::Low => notify_rust::Urgency::Low,
+            Urgency::Normal => notify_rust::Urgency::Normal,
+            Urgency::Critical => notify_rust::Urgency::Critical,
+        }
+    }
+}
+
+/// A daily window in local time, which may span midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    /// Seconds since midnight.
+    pub start: u32,
+    pub end: u32,
+}
+
+impl QuietHours {
+    pub fn contains(&self, seconds_of_day: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&seconds_of_day)
+        } else {
+            seconds_of_day >= self.start || seconds_of_day < self.end
+        }
+    }
+}
+
+/// Parses windows written as `22:00-07:30`.
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (start, end) = value
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid quiet hours {}, expected e.g. 22:00-07:00.", value))?;
+        Ok(QuietHours {
+            start: config::parse_time_of_day(start)?,
+            end: config::parse_time_of_day(end)?,
+        })
+    }
+}
+
+/// Text with `{name}` placeholders filled from the new wallpaper, `{{`/`}}` being literal braces.
+#[derive(Debug, Clone)]
+pub struct Template(template::Template);
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        template::Template::parse(value, &PLACEHOLDERS)
+            .map(Template)
+            .map_err(|err| format!("Invalid template {}: {}.", value, err))
+    }
+}
+
+impl Template {
+    /// Whether `{placeholder}` appears in the template.
+    pub fn uses(&self, placeholder: &str) -> bool {
+        self.0.uses(placeholder)
+    }
+
+    pub fn render(&self, wallpaper: &Wallpaper) -> String {
+        self.0.render(|name| wallpaper.placeholder_value(name))
+    }
+}
+
+/// A wallpaper that was just applied.
+#[derive(Debug)]
+pub struct Wallpaper<'a> {
+    pub output: Option<&'a str>,
+    pub path: &'a Path,
+    /// Folder of the source it was picked from.
+    pub source: Option<&'a Path>,
+}
+
+impl Wallpaper<'_> {
+    fn placeholder_value(&self, name: &str) -> String {
+        let lossy = |value: Option<&std::ffi::OsStr>| {
+            value
+                .map(|value| value.to_string_lossy().to_string())
+                .unwrap_or_default()
+        };
+        match name {
+            "filename" => lossy(self.path.file_name()),
+            "directory" => lossy(self.path.parent().map(Path::as_os_str)),
+            "path" => self.path.to_string_lossy().to_string(),
+            "resolution" => images::read_image_size(self.path)
+                .map(|size| size.to_string())
+                .unwrap_or_default(),
+            "source" => lossy(self.source.map(Path::as_os_str)),
+            "output" => self.output.unwrap_or_default().to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Buttons offered on the notification of a new wallpaper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Next,
+    /// Adds the wallpaper to the favourites.
+    Keep,
+    /// Bans the wallpaper.
+    NeverAgain,
+    /// Opens the wallpaper in the default image viewer.
+    Open,
+    /// Rates the wallpaper.
+    Rate(Rating),
+}
+
+impl Action {
+    /// Buttons offered unless configured otherwise.
+    pub const DEFAULT: [Action; 4] = [Action::Next, Action::Keep, Action::NeverAgain, Action::Open];
+
+    #[cfg(feature = "notifications")]
+    fn label(self) -> String {
+        match self {
+            Action::Next => "Next".to_string(),
+            Action::Keep => "Keep".to_string(),
+            Action::NeverAgain => "Never again".to_string(),
+            Action::Open => "Open".to_string(),
+            Action::Rate(rating) => "★".repeat(usize::from(rating.stars())),
+        }
+    }
+}
+
+/// The id of the action, also used to configure it.
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Next => write!(f, "next"),
+            Action::Keep => write!(f, "keep"),
+            Action::NeverAgain => write!(f, "never-again"),
+            Action::Open => write!(f, "open"),
+            Action::Rate(rating) => write!(f, "rate-{}", rating),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_lowercase();
+        match value.as_str() {
+            "next" => Ok(Action::Next),
+            "keep" => Ok(Action::Keep),
+            "never-again" => Ok(Action::NeverAgain),
+            "open" => Ok(Action::Open),
+            _ => value
+                .strip_prefix("rate-")
+                .and_then(|rating| rating.parse().ok())
+                .map(Action::Rate)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown action {}, expected next, keep, never-again, open or rate-1 to \
+                         rate-5.",
+                        value
+                    )
+                }),
+        }
+    }
+}
+
+type ActionHandler = Box<dyn Fn(Option<Action>, PathBuf) + Send + Sync>;
+
+static ACTION_HANDLER: OnceLock<ActionHandler> = OnceLock::new();
+
+static OFFERING_ACTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Offers the actions on the notifications of new wallpapers from now on. Clicked actions are
+/// passed to `handler` with the wallpaper they were clicked for, from a background thread, except
+/// `Open` which is handled right away. Once a notification stops offering its actions, clicked or
+/// closed, `handler` is passed `None`. Only the first handler is kept.
+pub fn set_action_handler(handler: impl Fn(Option<Action>, PathBuf) + Send + Sync + 'static) {
+    if ACTION_HANDLER.set(Box::new(handler)).is_err() {
+        tracing::error!("A notification action handler is already set");
+    }
+}
+
+/// How many notifications shown still offer their actions.
+pub fn offering_actions() -> usize {
+    OFFERING_ACTIONS.load(Ordering::SeqCst)
+}
+
+/// Whether a new wallpaper is notified right now, never without the `notifications` feature.
+pub fn notifies_wallpapers(notification_config: &NotificationConfig) -> bool {
+    let quiet = notification_config
+        .quiet_hours
+        .is_some_and(|quiet_hours| quiet_hours.contains(state::local_seconds_of_day(state::now())));
+    cfg!(feature = "notifications")
+        && notification_config.enabled
+        && notification_config.show != NotificationFilter::Errors
+        && !quiet
+}
+
+/// Name of the notification server, to check that notifications can be shown.
+pub fn server_name() -> Result<String, String> {
+    #[cfg(feature = "notifications")]
+    return notify_rust::get_server_information()
+        .map(|server| server.name)
+        .map_err(|err| err.to_string());
+    #[cfg(not(feature = "notifications"))]
+    Err("built without the notifications feature".to_string())
+}
+
+/// Shows an error until it is dismissed, unless only success notifications are wanted.
+#[cfg(feature = "notifications")]
+#[tracing::instrument]
+pub fn send_error_notification(body: &str, icon: &str, notification_config: &NotificationConfig) {
+    if !notification_config.enabled || notification_config.show == NotificationFilter::Success {
+        return;
+    }
+    let result = Notification::new()
+        .summary(APP_NAME)
+        .body(body)
+        .icon(icon)
+        .urgency(notify_rust::Urgency::Critical)
+        .timeout(i32::MAX)
+        .hint(Hint::Resident(true))
+        .show();
+    if result.is_err() {
+        error!("Failed to send notification.");
+    }
+}
+
+/// Shows the new wallpaper, with the actions once a handler is set. Nothing is shown during the
+/// quiet hours or when only errors are wanted.
+#[cfg(feature = "notifications")]
+#[tracing::instrument]
+pub fn send_wallpaper_changed_notification(
+    wallpaper: &Wallpaper,
+    notification_config: &NotificationConfig,
+) {
+    if !notifies_wallpapers(notification_config) {
+        return;
+    }
+    let body = render_body(wallpaper, notification_config);
+    let mut notification = Notification::new();
+    notification
+        .summary(&notification_config.summary.render(wallpaper))
+        .body(&body)
+        .icon(&wallpaper.path.to_string_lossy())
+        .urgency(notification_config.urgency.into())
+        .timeout(notification_config.timeout);
+
+    let handler = ACTION_HANDLER.get();
+    let Some(handler) = handler.filter(|_| !notification_config.actions.is_empty()) else {
+        if notification.show().is_err() {
+            error!("Failed to send notification.");
+        }
+        return;
+    };
+    for action in &notification_config.actions {
+        notification.action(&action.to_string(), &action.label());
+    }
+    let handle = match notification.show() {
+        Ok(handle) => handle,
+        Err(_) => {
+            error!("Failed to send notification.");
+            return;
+        }
+    };
+    let selected_file = wallpaper.path.to_path_buf();
+    OFFERING_ACTIONS.fetch_add(1, Ordering::SeqCst);
+    std::thread::spawn(move || {
+        // Waits for a single action, or for the notification to be closed.
+        handle.wait_for_action(|id| {
+            let Ok(action) = id.parse::<Action>() else {
+                return;
+            };
+            info!("{:?} clicked for {}", action, selected_file.display());
+            match action {
+                Action::Open => open(&selected_file),
+                _ => handler(Some(action), selected_file.clone()),
+            }
+        });
+        OFFERING_ACTIONS.fetch_sub(1, Ordering::SeqCst);
+        handler(None, selected_file);
+    });
+}
+
+/// Does nothing, the errors being logged by the caller already.
+#[cfg(not(feature = "notifications"))]
+pub fn send_error_notification(
+    _body: &str,
+    _icon: &str,
+    _notification_config: &NotificationConfig,
+) {
+}
+
+/// Logs what the notification would show, as long as new wallpapers are notified.
+#[cfg(not(feature = "notifications"))]
+pub fn send_wallpaper_changed_notification(
+    wallpaper: &Wallpaper,
+    notification_config: &NotificationConfig,
+) {
+    if notification_config.enabled && notification_config.show != NotificationFilter::Errors {
+        tracing::info!(
+            "{}: {}",
+            notification_config.summary.render(wallpaper),
+            render_body(wallpaper, notification_config)
+        );
+    }
+}
+
+/// The body of the notification, prefixed with the output unless the template shows it.
+fn render_body(wallpaper: &Wallpaper, notification_config: &NotificationConfig) -> String {
+    let body = notification_config.body.render(wallpaper);
+    match wallpaper.output {
+        Some(output) if !notification_config.body.uses("output") => {
+            format!("{}: {}", output, body)
+        }
+        _ => body,
+    }
+}
+
+/// Opens `path` with `xdg-open`.
+#[cfg(feature = "notifications")]
+#[tracing::instrument]
+fn open(path: &Path) {
+    match std::process::Command::new("xdg-open").arg(path).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => error!("xdg-open {} failed with {}", path.display(), status),
+        Err(err) => error!("Failed to run xdg-open: {}", err),
+    }
+}
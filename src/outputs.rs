@@ -0,0 +1,531 @@
+use std::fmt;
+use std::process::Command;
+
+use tracing::{info, warn};
+
+use crate::images::ImageSize;
+
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// Hz, when the compositor reports it.
+    pub refresh_rate: Option<f64>,
+}
+
+impl Output {
+    pub fn size(&self) -> ImageSize {
+        ImageSize {
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}x{})", self.name, self.width, self.height)
+    }
+}
+
+/// Lists the connected outputs through the compositor, using Hyprland when it is running and
+/// `wlr-randr` (wlr-output-management) otherwise.
+#[tracing::instrument]
+pub fn discover_outputs() -> Vec<Output> {
+    let outputs = if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        run_query(Command::new("hyprctl").args(["monitors", "-j"]))
+            .map(|stdout| parse_hyprctl_monitors(&stdout))
+    } else {
+        run_query(&mut Command::new("wlr-randr")).map(|stdout| parse_wlr_randr(&stdout))
+    };
+
+    let outputs = outputs.unwrap_or_default();
+    for output in &outputs {
+        info!("Found output {}", output);
+    }
+    outputs
+}
+
+#[tracing::instrument]
+pub fn run_query(command: &mut Command) -> Option<String> {
+    match command.output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).to_string())
+        }
+        Ok(output) => {
+            warn!(
+                "{:?} exited with {}: {}",
+                command.get_program(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            None
+        }
+        Err(err) => {
+            warn!("Failed to execute {:?}: {}", command.get_program(), err);
+            None
+        }
+    }
+}
+
+/// Parses lines such as `DP-1: 2560x1440, scale: 1, currently displaying: image: /a.png`.
+#[tracing::instrument(skip(stdout))]
+pub fn parse_swww_query(stdout: &str) -> Vec<Output> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+            let (name, rest) = line.split_once(':')?;
+            let (width, height) = parse_resolution(rest.split(',').next()?)?;
+            Some(Output {
+                name: name.trim().to_string(),
+                width,
+                height,
+                refresh_rate: None,
+            })
+        })
+        .collect()
+}
+
+/// Parses the array of monitors printed by `hyprctl monitors -j`, skipping disabled ones. Odd
+/// `transform`s are rotated by 90 or 270 degrees.
+#[tracing::instrument(skip(stdout))]
+fn parse_hyprctl_monitors(stdout: &str) -> Vec<Output> {
+    let monitors = match Json::parse(stdout) {
+        Ok(Json::Array(monitors)) => monitors,
+        Ok(_) => {
+            warn!("Expected an array of monitors from hyprctl");
+            return Vec::new();
+        }
+        Err(err) => {
+            warn!("Failed to read the monitors from hyprctl: {}", err);
+            return Vec::new();
+        }
+    };
+    monitors
+        .iter()
+        .filter(|monitor| monitor.get("disabled").and_then(Json::as_bool) != Some(true))
+        .filter_map(|monitor| {
+            let dimension = |key| {
+                let value = monitor.get(key)?.as_number()?;
+                (value >= 1.0 && value <= u32::MAX as f64).then_some(value as u32)
+            };
+            let (mut width, mut height) = (dimension("width")?, dimension("height")?);
+            let transform = monitor.get("transform").and_then(Json::as_number);
+            if transform.is_some_and(|transform| transform % 2.0 == 1.0) {
+                std::mem::swap(&mut width, &mut height);
+            }
+            Some(Output {
+                name: monitor.get("name")?.as_str()?.to_string(),
+                width,
+                height,
+                refresh_rate: monitor.get("refreshRate").and_then(Json::as_number),
+            })
+        })
+        .collect()
+}
+
+/// Parses `wlr-randr`, where each output header is unindented and the current mode is marked
+/// with `current`, e.g. `1920x1080 px, 60.000000 Hz (preferred, current)`. Disabled outputs have
+/// no current mode and are skipped.
+#[tracing::instrument(skip(stdout))]
+fn parse_wlr_randr(stdout: &str) -> Vec<Output> {
+    let mut outputs: Vec<(Output, bool)> = Vec::new();
+    for line in stdout.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            if let Some(name) = line.split_whitespace().next() {
+                let output = Output {
+                    name: name.to_string(),
+                    width: 0,
+                    height: 0,
+                    refresh_rate: None,
+                };
+                outputs.push((output, false));
+            }
+            continue;
+        }
+
+        let Some((output, rotated)) = outputs.last_mut() else {
+            continue;
+        };
+        let line = line.trim();
+        if let Some(transform) = line.strip_prefix("Transform:") {
+            *rotated = ["90", "270", "flipped-90", "flipped-270"].contains(&transform.trim());
+        } else if line.contains("current") {
+            if let Some((width, height)) = line.split_whitespace().next().and_then(parse_resolution)
+            {
+                output.width = width;
+                output.height = height;
+                output.refresh_rate = line
+                    .split_once(" Hz")
+                    .and_then(|(before, _)| before.rsplit([' ', ',']).next())
+                    .and_then(|rate| rate.parse().ok());
+            }
+        }
+    }
+    outputs
+        .into_iter()
+        .filter(|(output, _)| output.width > 0 && output.height > 0)
+        .map(|(mut output, rotated)| {
+            if rotated {
+                std::mem::swap(&mut output.width, &mut output.height);
+            }
+            output
+        })
+        .collect()
+}
+
+fn parse_resolution(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.trim().split_once('x')?;
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+/// The JSON values printed by `hyprctl -j`, read without pulling in a JSON library.
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = JsonParser {
+            chars: text.chars().collect(),
+            position: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(value),
+            Some(char) => Err(parser.unexpected(char)),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Json::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl JsonParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let char = self.peek()?;
+        self.position += 1;
+        Some(char)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn unexpected(&self, char: char) -> String {
+        format!("unexpected {:?} at {}", char, self.position)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.next() {
+            Some(char) if char == expected => Ok(()),
+            Some(char) => Err(self.unexpected(char)),
+            None => Err(format!("expected {:?} before the end", expected)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some(char) if char == '-' || char.is_ascii_digit() => self.number(),
+            Some(char) => Err(self.unexpected(char)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.expect(':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(members)),
+                Some(char) => return Err(self.unexpected(char)),
+                None => return Err("unclosed object".to_string()),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(items)),
+                Some(char) => return Err(self.unexpected(char)),
+                None => return Err("unclosed array".to_string()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let escaped = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let hex = self
+                                .chars
+                                .get(self.position..self.position + 4)
+                                .map(|hex| hex.iter().collect::<String>())
+                                .ok_or("truncated \\u escape")?;
+                            self.position += 4;
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        Some(char) => char,
+                        None => return Err("unclosed string".to_string()),
+                    };
+                    string.push(escaped);
+                }
+                Some(char) => string.push(char),
+                None => return Err("unclosed string".to_string()),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|char| char.is_ascii_digit() || "+-.eE".contains(char))
+        {
+            self.position += 1;
+        }
+        let number = self.chars[start..self.position].iter().collect::<String>();
+        number
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| format!("invalid number {} at {}", number, start))
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        let end = self.position + keyword.chars().count();
+        if self
+            .chars
+            .get(self.position..end)
+            .is_some_and(|chars| chars.iter().copied().eq(keyword.chars()))
+        {
+            self.position = end;
+            Ok(value)
+        } else {
+            Err(self.unexpected(self.chars[self.position]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizes(outputs: &[Output]) -> Vec<(&str, u32, u32)> {
+        outputs
+            .iter()
+            .map(|output| (output.name.as_str(), output.width, output.height))
+            .collect()
+    }
+
+    #[test]
+    fn parses_swww_queries() {
+        let old = "DP-1: 2560x1440, scale: 1, currently displaying: image: /a.png\n\
+                   HDMI-A-1: 1080x1920, scale: 1, currently displaying: color: 000000\n";
+        assert_eq!(
+            sizes(&parse_swww_query(old)),
+            [("DP-1", 2560, 1440), ("HDMI-A-1", 1080, 1920)]
+        );
+
+        let new = ": DP-1: 2560x1440, scale: 2, currently displaying: image: /a: b.png\n\n";
+        assert_eq!(sizes(&parse_swww_query(new)), [("DP-1", 2560, 1440)]);
+        assert!(parse_swww_query("Error: no outputs\n").is_empty());
+    }
+
+    #[test]
+    fn parses_hyprctl_monitors() {
+        let stdout = r#"[{
+            "id": 0,
+            "name": "DP-1",
+            "description": "Dell \"U2719D\" é",
+            "width": 2560,
+            "height": 1440,
+            "refreshRate": 143.91200,
+            "x": 0,
+            "y": 0,
+            "scale": 1.00,
+            "transform": 0,
+            "disabled": false,
+            "availableModes": ["2560x1440@143.91Hz", "1920x1080@60.00Hz"]
+        },{
+            "id": 1,
+            "name": "HDMI-A-1",
+            "width": 1920,
+            "height": 1080,
+            "refreshRate": 60.0,
+            "transform": 3,
+            "activeWorkspace": {"id": 2, "name": "2"},
+            "mirrorOf": null,
+            "disabled": false
+        },{
+            "id": 2,
+            "name": "eDP-1",
+            "width": 1920,
+            "height": 1200,
+            "refreshRate": 60.0,
+            "transform": 2,
+            "disabled": true
+        }]"#;
+        let outputs = parse_hyprctl_monitors(stdout);
+        assert_eq!(
+            sizes(&outputs),
+            [("DP-1", 2560, 1440), ("HDMI-A-1", 1080, 1920)]
+        );
+        assert_eq!(outputs[0].refresh_rate, Some(143.912));
+
+        assert!(parse_hyprctl_monitors("[]").is_empty());
+        assert!(parse_hyprctl_monitors("Monitor DP-1 (ID 0):").is_empty());
+        assert!(
+            parse_hyprctl_monitors(r#"[{"name": "DP-1", "width": 0, "height": 0}]"#).is_empty()
+        );
+    }
+
+    #[test]
+    fn parses_wlr_randr() {
+        let stdout = "\
+DP-1 \"Dell Inc. DELL U2719D\"
+  Make: Dell Inc.
+  Enabled: yes
+  Transform: 90
+  Modes:
+    1920x1080 px, 60.000000 Hz
+    2560x1440 px, 59.951000 Hz (preferred, current)
+  Position: 0,0
+  Scale: 1.000000
+HDMI-A-1 \"Unknown\"
+  Enabled: no
+  Modes:
+    1920x1080 px, 60.000000 Hz (preferred)
+eDP-1 \"Laptop\"
+  Enabled: yes
+  Modes:
+    1920x1200 px, 60.001000 Hz (current)
+  Transform: flipped-180
+";
+        let outputs = parse_wlr_randr(stdout);
+        assert_eq!(
+            sizes(&outputs),
+            [("DP-1", 1440, 2560), ("eDP-1", 1920, 1200)]
+        );
+        assert_eq!(outputs[0].refresh_rate, Some(59.951));
+        assert_eq!(outputs[1].refresh_rate, Some(60.001));
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        for text in [
+            "",
+            "[",
+            "[1,]",
+            "{\"a\" 1}",
+            "\"unclosed",
+            "tru",
+            "[1] 2",
+            "-",
+        ] {
+            assert!(Json::parse(text).is_err(), "{:?}", text);
+        }
+        assert_eq!(
+            Json::parse(r#" {"a": [true, null, -1.5e2, "\nA"]} "#),
+            Ok(Json::Object(vec![(
+                "a".to_string(),
+                Json::Array(vec![
+                    Json::Bool(true),
+                    Json::Null,
+                    Json::Number(-150.0),
+                    Json::String("\nA".to_string()),
+                ])
+            )]))
+        );
+    }
+}
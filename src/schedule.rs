@@ -0,0 +1,285 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::config;
+use crate::sources::Source;
+use crate::state;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Unix day number of 2000-01-01, whose noon UTC is the J2000 epoch.
+const J2000_DAY: i64 = 10957;
+
+/// Where the sun is computed for, in degrees, north and east being positive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+/// When a period starts each day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartTime {
+    /// Seconds since local midnight.
+    Fixed(u32),
+    /// Seconds after sunrise or sunset, before it when negative.
+    Sun(SunEvent, i64),
+}
+
+impl StartTime {
+    pub fn uses_sun(&self) -> bool {
+        matches!(self, StartTime::Sun(..))
+    }
+}
+
+impl fmt::Display for StartTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartTime::Fixed(seconds) => {
+                write!(f, "{:02}:{:02}", seconds / 3600, seconds / 60 % 60)
+            }
+            StartTime::Sun(event, offset) => {
+                let event = match event {
+                    SunEvent::Sunrise => "sunrise",
+                    SunEvent::Sunset => "sunset",
+                };
+                let duration = config::format_duration(Duration::from_secs(offset.unsigned_abs()));
+                match offset {
+                    0 => write!(f, "{}", event),
+                    1.. => write!(f, "{}+{}", event, duration),
+                    _ => write!(f, "{}-{}", event, duration),
+                }
+            }
+        }
+    }
+}
+
+/// Parses `HH:MM`, `sunrise` or `sunset`, the latter optionally shifted such as `sunset-30m`.
+impl FromStr for StartTime {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_lowercase();
+        let (event, offset) = if let Some(offset) = value.strip_prefix("sunrise") {
+            (SunEvent::Sunrise, offset)
+        } else if let Some(offset) = value.strip_prefix("sunset") {
+            (SunEvent::Sunset, offset)
+        } else {
+            return config::parse_time_of_day(&value).map(StartTime::Fixed);
+        };
+        let invalid = || {
+            format!(
+                "Invalid start {}, expected HH:MM, sunrise or sunset, optionally shifted such as \
+                 sunset-30m.",
+                value
+            )
+        };
+        let offset = match offset.trim_start().chars().next() {
+            None => 0,
+            Some(sign @ ('+' | '-')) => {
+                let duration = config::parse_duration(&offset.trim_start()[1..])
+                    .map_err(|_| invalid())?
+                    .as_secs() as i64;
+                if sign == '-' {
+                    -duration
+                } else {
+                    duration
+                }
+            }
+            Some(_) => return Err(invalid()),
+        };
+        Ok(StartTime::Sun(event, offset))
+    }
+}
+
+/// Part of the day picking from its own sources, until the next period starts.
+#[derive(Debug, Clone)]
+pub struct Period {
+    /// Shown in the logs and the status, the start time when unset.
+    pub name: Option<String>,
+    pub start: StartTime,
+    /// Folders picked from during the period.
+    pub sources: Vec<Source>,
+    /// Configured sources with any of these tags are picked from too.
+    pub tags: Vec<String>,
+}
+
+impl Period {
+    pub fn includes(&self, source: &Source) -> bool {
+        self.sources
+            .iter()
+            .any(|period_source| period_source.path == source.path)
+            || source.tags.iter().any(|tag| self.tags.contains(tag))
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{}", self.start),
+        }
+    }
+}
+
+/// Periods of the day, each starting at a local time or relative to the sun.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// Needed by periods starting at sunrise or sunset.
+    pub location: Option<Location>,
+    pub periods: Vec<Period>,
+}
+
+impl Schedule {
+    /// The period started last at `timestamp`, `None` when no period starts around it, e.g.
+    /// during a polar night with only sun relative periods.
+    pub fn current_period(&self, timestamp: i64) -> Option<&Period> {
+        self.starts_around(timestamp)
+            .filter(|(start, _)| *start <= timestamp)
+            .max_by_key(|(start, _)| *start)
+            .map(|(_, period)| period)
+    }
+
+    /// When the next period starts after `timestamp`.
+    pub fn next_boundary(&self, timestamp: i64) -> Option<i64> {
+        self.starts_around(timestamp)
+            .map(|(start, _)| start)
+            .filter(|start| *start > timestamp)
+            .min()
+    }
+
+    /// Sources of every period, which are scanned along with the configured ones.
+    pub fn sources(&self) -> impl Iterator<Item = &Source> {
+        self.periods.iter().flat_map(|period| &period.sources)
+    }
+
+    fn starts_around(&self, timestamp: i64) -> impl Iterator<Item = (i64, &Period)> {
+        let starts = self.periods.iter().map(|period| period.start).collect();
+        starts_around(self.location, starts, timestamp)
+            .map(|(start, index)| (start, &self.periods[index]))
+    }
+}
+
+/// Unix time of each of `starts`, with its index, from the local day before `timestamp` to the
+/// day after. Sun relative starts are skipped without a location or on days without the event.
+pub fn starts_around(
+    location: Option<Location>,
+    starts: Vec<StartTime>,
+    timestamp: i64,
+) -> impl Iterator<Item = (i64, usize)> {
+    let today = (timestamp + state::local_utc_offset(timestamp)).div_euclid(DAY);
+    (today - 1..=today + 1).flat_map(move |day| {
+        starts
+            .clone()
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(index, start)| Some((start_on(location, day, start)?, index)))
+    })
+}
+
+fn start_on(location: Option<Location>, day: i64, start: StartTime) -> Option<i64> {
+    match start {
+        StartTime::Fixed(seconds) => Some(local_to_utc(day * DAY + i64::from(seconds))),
+        StartTime::Sun(event, offset) => Some(sun_event(location?, day, event)? + offset),
+    }
+}
+
+/// Local `HH:MM` of a Unix timestamp.
+pub fn format_local_time(timestamp: i64) -> String {
+    StartTime::Fixed(state::local_seconds_of_day(timestamp)).to_string()
+}
+
+/// Unix time of a local date and time, given in seconds since the Unix epoch as if it were UTC.
+pub fn local_to_utc(local: i64) -> i64 {
+    let guess = local - state::local_utc_offset(local);
+    local - state::local_utc_offset(guess)
+}
+
+/// Unix time of sunrise or sunset on the local `day` since the Unix epoch, `None` when the sun
+/// doesn't rise or set that day. Uses the sunrise equation, accurate to about a minute.
+fn sun_event(location: Location, day: i64, event: SunEvent) -> Option<i64> {
+    let sin = |degrees: f64| degrees.to_radians().sin();
+    let cos = |degrees: f64| degrees.to_radians().cos();
+
+    let mean_solar_noon = (day - J2000_DAY) as f64 - location.longitude / 360.0;
+    let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_noon).rem_euclid(360.0);
+    let center = 1.9148 * sin(mean_anomaly)
+        + 0.0200 * sin(2.0 * mean_anomaly)
+        + 0.0003 * sin(3.0 * mean_anomaly);
+    let ecliptic_longitude = (mean_anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let transit =
+        mean_solar_noon + 0.0053 * sin(mean_anomaly) - 0.0069 * sin(2.0 * ecliptic_longitude);
+    let declination = (sin(ecliptic_longitude) * sin(23.4397)).asin();
+
+    let cos_hour_angle = (sin(-0.833) - sin(location.latitude) * declination.sin())
+        / (cos(location.latitude) * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    let days_since_j2000 = match event {
+        SunEvent::Sunrise => transit - hour_angle / 360.0,
+        SunEvent::Sunset => transit + hour_angle / 360.0,
+    };
+    Some(((J2000_DAY as f64 + 0.5 + days_since_j2000) * DAY as f64).round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: Location = Location {
+        latitude: 51.5074,
+        longitude: -0.1278,
+    };
+    const TROMSO: Location = Location {
+        latitude: 69.6496,
+        longitude: 18.956,
+    };
+    /// 2024-06-21 and 2024-12-21 as days since the Unix epoch.
+    const SUMMER_SOLSTICE: i64 = 19895;
+    const WINTER_SOLSTICE: i64 = 20078;
+
+    #[test]
+    fn computes_sunrise_and_sunset() {
+        // 03:43 and 20:21 UTC, as published for London that day.
+        let sunrise = sun_event(LONDON, SUMMER_SOLSTICE, SunEvent::Sunrise).unwrap();
+        let sunset = sun_event(LONDON, SUMMER_SOLSTICE, SunEvent::Sunset).unwrap();
+        assert!(
+            (sunrise - 1718941380).abs() <= 120,
+            "sunrise at {}",
+            sunrise
+        );
+        assert!((sunset - 1719001260).abs() <= 120, "sunset at {}", sunset);
+    }
+
+    #[test]
+    fn polar_day_and_night_have_no_sun_events() {
+        for day in [SUMMER_SOLSTICE, WINTER_SOLSTICE] {
+            assert_eq!(sun_event(TROMSO, day, SunEvent::Sunrise), None);
+            assert_eq!(sun_event(TROMSO, day, SunEvent::Sunset), None);
+        }
+        assert!(sun_event(LONDON, WINTER_SOLSTICE, SunEvent::Sunrise).is_some());
+    }
+
+    #[test]
+    fn skips_sun_relative_starts_without_the_event() {
+        let starts = vec![StartTime::Fixed(0), StartTime::Sun(SunEvent::Sunrise, 0)];
+        let timestamp = WINTER_SOLSTICE * DAY + DAY / 2;
+        let indices = |location| {
+            starts_around(location, starts.clone(), timestamp)
+                .map(|(_, index)| index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(indices(Some(TROMSO)), [0, 0, 0]);
+        assert_eq!(indices(None), [0, 0, 0]);
+        assert_eq!(indices(Some(LONDON)), [0, 1, 0, 1, 0, 1]);
+    }
+}
@@ -0,0 +1,628 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rand_core::OsRng;
+use rand_distr::{Distribution, Uniform};
+use tracing::info;
+
+use crate::filters::SizeFilter;
+use crate::images::ImageSize;
+use crate::marks::{MarkSet, Rating};
+use crate::sources::SourceWallpapers;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionMode {
+    /// Every change draws from all the wallpapers, as likely as the `Strategy` makes them.
+    Random,
+    /// Goes through every wallpaper once, in a random order, before repeating any.
+    Shuffle,
+}
+
+impl fmt::Display for SelectionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectionMode::Random => write!(f, "random"),
+            SelectionMode::Shuffle => write!(f, "shuffle"),
+        }
+    }
+}
+
+impl FromStr for SelectionMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "random" => Ok(SelectionMode::Random),
+            "shuffle" => Ok(SelectionMode::Shuffle),
+            _ => Err(format!(
+                "Unknown selection mode {}, expected random or shuffle.",
+                value
+            )),
+        }
+    }
+}
+
+/// How likely each wallpaper of a folder is in `random` mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Every wallpaper is equally likely.
+    Uniform,
+    /// Favourites are `favorite_weight` times as likely as the other wallpapers.
+    Favorites,
+    /// Weighted by the rating, favourites getting `favorite_weight` on top.
+    Rating,
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::Uniform => write!(f, "uniform"),
+            Strategy::Favorites => write!(f, "favorites"),
+            Strategy::Rating => write!(f, "rating"),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "uniform" => Ok(Strategy::Uniform),
+            "favorites" => Ok(Strategy::Favorites),
+            "rating" => Ok(Strategy::Rating),
+            _ => Err(format!(
+                "Unknown selection strategy {}, expected uniform, favorites or rating.",
+                value
+            )),
+        }
+    }
+}
+
+/// Weight of a rating in the `rating` strategy.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RatingWeight {
+    /// As many as the stars.
+    Linear,
+    /// The square of the stars.
+    Quadratic,
+    /// Doubles with each star, from 1 for one star.
+    Exponential,
+    /// Given for each rating from 1 to 5.
+    Table([f64; 5]),
+}
+
+impl RatingWeight {
+    pub fn weight(&self, rating: Rating) -> f64 {
+        let stars = f64::from(rating.stars());
+        match self {
+            RatingWeight::Linear => stars,
+            RatingWeight::Quadratic => stars * stars,
+            RatingWeight::Exponential => 2f64.powf(stars - 1.0),
+            RatingWeight::Table(weights) => weights[usize::from(rating.stars() - 1)],
+        }
+    }
+}
+
+impl fmt::Display for RatingWeight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RatingWeight::Linear => write!(f, "linear"),
+            RatingWeight::Quadratic => write!(f, "quadratic"),
+            RatingWeight::Exponential => write!(f, "exponential"),
+            RatingWeight::Table(weights) => write!(
+                f,
+                "{}",
+                weights
+                    .iter()
+                    .map(f64::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+}
+
+/// Parses `linear`, `quadratic`, `exponential` or five comma separated weights such as
+/// `0,1,2,4,8`.
+impl FromStr for RatingWeight {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "linear" => Ok(RatingWeight::Linear),
+            "quadratic" => Ok(RatingWeight::Quadratic),
+            "exponential" => Ok(RatingWeight::Exponential),
+            weights => weights
+                .split(',')
+                .map(|weight| {
+                    weight
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|weight| weight.is_finite() && *weight >= 0.0)
+                })
+                .collect::<Option<Vec<_>>>()
+                .and_then(|weights| weights.try_into().ok())
+                .map(RatingWeight::Table)
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid rating weight {}, expected linear, quadratic, exponential or \
+                         five positive numbers such as 0,1,2,4,8.",
+                        value
+                    )
+                }),
+        }
+    }
+}
+
+/// Everything deciding how likely each wallpaper is in `random` mode.
+#[derive(Debug, Clone, Copy)]
+pub struct Weights<'a> {
+    pub strategy: Strategy,
+    pub favorites: &'a MarkSet,
+    pub favorite_weight: f64,
+    pub ratings: &'a MarkSet<Rating>,
+    pub rating_weight: &'a RatingWeight,
+    /// Weight of unrated wallpapers, the one of a 3 star rating when unset.
+    pub unrated_weight: Option<f64>,
+}
+
+impl Weights<'_> {
+    pub fn weight(&self, wallpaper: &Path) -> f64 {
+        let favorite_weight = if self.favorites.contains(wallpaper) {
+            self.favorite_weight
+        } else {
+            1.0
+        };
+        match self.strategy {
+            Strategy::Uniform => 1.0,
+            Strategy::Favorites => favorite_weight,
+            Strategy::Rating => {
+                let rating_weight = match self.ratings.get(wallpaper) {
+                    Some(rating) => self.rating_weight.weight(*rating),
+                    None => self
+                        .unrated_weight
+                        .unwrap_or_else(|| self.rating_weight.weight(Rating::NEUTRAL)),
+                };
+                rating_weight * favorite_weight
+            }
+        }
+    }
+}
+
+/// Picks an index with a probability proportional to its weight, `None` when all are zero.
+#[tracing::instrument]
+pub fn choose_weighted_index(weights: &[f64]) -> Option<usize> {
+    let total_weight = weights.iter().sum::<f64>();
+    if total_weight <= 0.0 {
+        return None;
+    }
+
+    let mut remaining_weight = Uniform::new(0.0, total_weight).sample(&mut OsRng);
+    weights
+        .iter()
+        .position(|weight| {
+            remaining_weight -= weight;
+            remaining_weight < 0.0
+        })
+        .or_else(|| weights.iter().rposition(|weight| *weight > 0.0))
+}
+
+fn shuffle<T>(items: &mut [T]) {
+    for index in (1..items.len()).rev() {
+        let other_index = Uniform::new_inclusive(0, index).sample(&mut OsRng);
+        items.swap(index, other_index);
+    }
+}
+
+#[derive(Debug)]
+struct ShuffleEntry {
+    path: PathBuf,
+    played: bool,
+}
+
+/// A persisted random order of the library. Wallpapers are played in that order and the order
+/// is reshuffled once all of them have been shown.
+#[derive(Debug, Default)]
+pub struct ShuffleBag {
+    entries: Vec<ShuffleEntry>,
+}
+
+impl ShuffleBag {
+    pub fn from_entries(entries: impl Iterator<Item = (PathBuf, bool)>) -> Self {
+        let entries = entries
+            .map(|(path, played)| ShuffleEntry { path, played })
+            .collect();
+        ShuffleBag { entries }
+    }
+
+    /// Parses the `played<TAB>path` lines older versions kept next to the cache.
+    pub fn parse_legacy(content: &str) -> Self {
+        Self::from_entries(
+            content
+                .lines()
+                .filter_map(|line| line.split_once('\t'))
+                .map(|(played, path)| (PathBuf::from(path), played == "1")),
+        )
+    }
+
+    /// The wallpapers in play order, with whether they were played in the current round.
+    pub fn entries(&self) -> impl Iterator<Item = (&PathBuf, bool)> {
+        self.entries.iter().map(|entry| (&entry.path, entry.played))
+    }
+
+    /// Drops wallpapers that are gone from the library and slots new ones in at random places
+    /// among the ones still to be played.
+    #[tracing::instrument(skip(self, wallpapers))]
+    pub fn reconcile<'a>(&mut self, wallpapers: impl Iterator<Item = &'a PathBuf>) {
+        let wallpapers = wallpapers.collect::<HashSet<_>>();
+        let previous_count = self.entries.len();
+        self.entries
+            .retain(|entry| wallpapers.contains(&entry.path));
+        let removed_count = previous_count - self.entries.len();
+
+        let known_wallpapers = self
+            .entries
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect::<HashSet<_>>();
+        let mut new_wallpapers = wallpapers
+            .into_iter()
+            .filter(|file_path| !known_wallpapers.contains(*file_path))
+            .collect::<Vec<_>>();
+        new_wallpapers.sort();
+        for file_path in &new_wallpapers {
+            let index = Uniform::new_inclusive(0, self.entries.len()).sample(&mut OsRng);
+            self.entries.insert(
+                index,
+                ShuffleEntry {
+                    path: (*file_path).clone(),
+                    played: false,
+                },
+            );
+        }
+
+        if removed_count > 0 || !new_wallpapers.is_empty() {
+            info!(
+                "Shuffle order updated: {} removed, {} added",
+                removed_count,
+                new_wallpapers.len()
+            );
+        }
+    }
+
+    /// Takes the next unplayed wallpaper among `candidates`. When every wallpaper of `library`
+    /// has been played, those are reshuffled into a new round first.
+    #[tracing::instrument(skip(self, library, candidates))]
+    pub fn next(&mut self, library: &[PathBuf], candidates: &[PathBuf]) -> Option<PathBuf> {
+        if let Some(file_path) = self.take_next(candidates) {
+            return Some(file_path);
+        }
+
+        info!("Every wallpaper has been shown, reshuffling");
+        let library = library.iter().collect::<HashSet<_>>();
+        let (mut round, rest): (Vec<_>, Vec<_>) = self
+            .entries
+            .drain(..)
+            .partition(|entry| library.contains(&entry.path));
+        shuffle(&mut round);
+        for entry in &mut round {
+            entry.played = false;
+        }
+        self.entries = rest;
+        self.entries.extend(round);
+
+        self.take_next(candidates)
+    }
+
+    fn take_next(&mut self, candidates: &[PathBuf]) -> Option<PathBuf> {
+        let candidates = candidates.iter().collect::<HashSet<_>>();
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| !entry.played && candidates.contains(&entry.path))?;
+        entry.played = true;
+        Some(entry.path.clone())
+    }
+}
+
+/// The `wallpapers` that are neither banned nor in `excluded_wallpapers`.
+#[tracing::instrument(skip(wallpapers, banned))]
+pub fn get_possible_wallpapers(
+    wallpapers: &[PathBuf],
+    excluded_wallpapers: &[&PathBuf],
+    banned: &MarkSet,
+) -> Vec<PathBuf> {
+    wallpapers
+        .iter()
+        .filter(|file_path| !banned.contains(file_path))
+        .filter(|file_path| !excluded_wallpapers.contains(file_path))
+        .cloned()
+        .collect::<Vec<_>>()
+}
+
+#[tracing::instrument(skip(possible_wallpapers, wallpaper_sizes))]
+pub fn filter_by_size(
+    possible_wallpapers: Vec<PathBuf>,
+    wallpaper_sizes: &HashMap<PathBuf, ImageSize>,
+    size_filter: &SizeFilter,
+    output_size: Option<ImageSize>,
+) -> Vec<PathBuf> {
+    possible_wallpapers
+        .into_iter()
+        .filter(|file_path| {
+            wallpaper_sizes
+                .get(file_path)
+                .is_some_and(|size| size_filter.accepts(*size, output_size))
+        })
+        .collect()
+}
+
+/// Picks a source by weight among the ones with wallpapers left, then a wallpaper within it.
+/// Without a shuffle bag, the wallpaper is drawn according to `weights`. Dynamic sets only offer
+/// their current frame, which is taken as is.
+#[tracing::instrument(skip(possible_wallpapers, shuffle_bag, weights))]
+pub fn choose_wallpaper(
+    possible_wallpapers: &[(&SourceWallpapers, Vec<PathBuf>)],
+    shuffle_bag: Option<&mut ShuffleBag>,
+    weights: &Weights,
+) -> Option<PathBuf> {
+    let source_weights = possible_wallpapers
+        .iter()
+        .map(|(library, wallpapers)| {
+            if wallpapers.is_empty() {
+                0.0
+            } else {
+                library.source.weight
+            }
+        })
+        .collect::<Vec<_>>();
+    let (library, wallpapers) = &possible_wallpapers[choose_weighted_index(&source_weights)?];
+    if library.dynamic_set.is_some() {
+        return wallpapers.first().cloned();
+    }
+
+    match shuffle_bag {
+        Some(shuffle_bag) => shuffle_bag.next(&library.wallpapers, wallpapers),
+        None => choose_random_wallpaper(wallpapers, weights).cloned(),
+    }
+}
+
+#[tracing::instrument(skip(weights))]
+fn choose_random_wallpaper<'a>(
+    possible_wallpapers: &'a [PathBuf],
+    weights: &Weights,
+) -> Option<&'a PathBuf> {
+    let index = match weights.strategy {
+        Strategy::Uniform => None,
+        _ => choose_weighted_index(
+            &possible_wallpapers
+                .iter()
+                .map(|wallpaper| weights.weight(wallpaper))
+                .collect::<Vec<_>>(),
+        ),
+    };
+    let index = match index {
+        Some(index) => index,
+        // Uniform, or only wallpapers with a weight of zero left.
+        None if !possible_wallpapers.is_empty() => {
+            Uniform::new(0, possible_wallpapers.len()).sample(&mut OsRng)
+        }
+        None => return None,
+    };
+    possible_wallpapers.get(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marks::Mark;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn sorted(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        paths.sort();
+        paths
+    }
+
+    fn play_round(bag: &mut ShuffleBag, library: &[PathBuf]) -> Vec<PathBuf> {
+        library
+            .iter()
+            .map(|_| bag.next(library, library).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parses_rating_weights() {
+        assert_eq!("Linear".parse(), Ok(RatingWeight::Linear));
+        assert_eq!(" quadratic ".parse(), Ok(RatingWeight::Quadratic));
+        assert_eq!("exponential".parse(), Ok(RatingWeight::Exponential));
+        assert_eq!(
+            "0, 1,2,4,8.5".parse(),
+            Ok(RatingWeight::Table([0.0, 1.0, 2.0, 4.0, 8.5]))
+        );
+        for value in [
+            "",
+            "cubic",
+            "1,2,3,4",
+            "1,2,3,4,5,6",
+            "0,1,2,-4,8",
+            "0,1,x,4,8",
+            "1,2,3,4,inf",
+        ] {
+            assert!(value.parse::<RatingWeight>().is_err(), "{:?}", value);
+        }
+
+        let table = RatingWeight::Table([0.0, 1.0, 2.0, 4.0, 8.5]);
+        assert_eq!(table.to_string().parse(), Ok(table));
+    }
+
+    #[test]
+    fn weighs_ratings() {
+        let stars = |weight: &RatingWeight| {
+            (1..=5)
+                .map(|stars| weight.weight(Rating::new(stars).unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(stars(&RatingWeight::Linear), [1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(stars(&RatingWeight::Quadratic), [1.0, 4.0, 9.0, 16.0, 25.0]);
+        assert_eq!(
+            stars(&RatingWeight::Exponential),
+            [1.0, 2.0, 4.0, 8.0, 16.0]
+        );
+        assert_eq!(
+            stars(&RatingWeight::Table([0.0, 1.0, 2.0, 4.0, 8.0])),
+            [0.0, 1.0, 2.0, 4.0, 8.0]
+        );
+    }
+
+    #[test]
+    fn weighs_wallpapers_by_strategy() {
+        let mut favorites = MarkSet::default();
+        favorites.insert(PathBuf::from("favorite"));
+        favorites.insert(PathBuf::from("rated favorite"));
+        let ratings = MarkSet::from_marks([
+            Mark::new(PathBuf::from("rated"), Rating::MAX),
+            Mark::new(PathBuf::from("rated favorite"), Rating::MIN),
+        ]);
+        let mut weights = Weights {
+            strategy: Strategy::Uniform,
+            favorites: &favorites,
+            favorite_weight: 3.0,
+            ratings: &ratings,
+            rating_weight: &RatingWeight::Quadratic,
+            unrated_weight: None,
+        };
+        let weigh = |weights: &Weights| {
+            ["plain", "favorite", "rated", "rated favorite"]
+                .map(|wallpaper| weights.weight(Path::new(wallpaper)))
+        };
+
+        assert_eq!(weigh(&weights), [1.0, 1.0, 1.0, 1.0]);
+        weights.strategy = Strategy::Favorites;
+        assert_eq!(weigh(&weights), [1.0, 3.0, 1.0, 3.0]);
+        // Unrated wallpapers weigh as much as a neutral rating by default.
+        weights.strategy = Strategy::Rating;
+        assert_eq!(weigh(&weights), [9.0, 27.0, 25.0, 3.0]);
+        weights.unrated_weight = Some(0.5);
+        assert_eq!(weigh(&weights), [0.5, 1.5, 25.0, 3.0]);
+    }
+
+    #[test]
+    fn chooses_weighted_indexes() {
+        assert_eq!(choose_weighted_index(&[]), None);
+        assert_eq!(choose_weighted_index(&[0.0, 0.0]), None);
+        for _ in 0..100 {
+            assert_eq!(choose_weighted_index(&[0.0, 2.0, 0.0]), Some(1));
+            assert_ne!(choose_weighted_index(&[1.0, 0.0, 1.0]), Some(1));
+        }
+    }
+
+    #[test]
+    fn falls_back_to_uniform_when_every_weight_is_zero() {
+        let ratings = MarkSet::default();
+        let weights = Weights {
+            strategy: Strategy::Rating,
+            favorites: &MarkSet::default(),
+            favorite_weight: 2.0,
+            ratings: &ratings,
+            rating_weight: &RatingWeight::Linear,
+            unrated_weight: Some(0.0),
+        };
+        let wallpapers = paths(&["a", "b", "c"]);
+        let mut chosen = (0..200)
+            .filter_map(|_| choose_random_wallpaper(&wallpapers, &weights).cloned())
+            .collect::<Vec<_>>();
+        chosen.sort();
+        chosen.dedup();
+        assert_eq!(chosen, wallpapers);
+        assert_eq!(choose_random_wallpaper(&[], &weights), None);
+    }
+
+    #[test]
+    fn shuffle_rounds_show_every_wallpaper_once() {
+        let library = paths(&["a", "b", "c", "d", "e"]);
+        let mut bag = ShuffleBag::default();
+        bag.reconcile(library.iter());
+        assert_eq!(
+            sorted(bag.entries().map(|(path, _)| path.clone()).collect()),
+            library
+        );
+
+        for _ in 0..3 {
+            assert_eq!(sorted(play_round(&mut bag, &library)), library);
+            assert!(bag.entries().all(|(_, played)| played));
+        }
+    }
+
+    #[test]
+    fn shuffle_bag_refills_when_empty() {
+        let library = paths(&["a", "b"]);
+        let mut bag = ShuffleBag::from_entries(library.iter().map(|path| (path.clone(), true)));
+        let next = bag.next(&library, &library).unwrap();
+        assert!(library.contains(&next));
+        assert_eq!(
+            bag.entries().filter(|(_, played)| !played).count(),
+            1,
+            "The new round has one wallpaper left after taking one."
+        );
+
+        assert_eq!(ShuffleBag::default().next(&library, &library), None);
+        assert_eq!(bag.next(&library, &paths(&["z"])), None);
+    }
+
+    #[test]
+    fn shuffle_bag_only_takes_candidates() {
+        let library = paths(&["a", "b", "c"]);
+        let mut bag = ShuffleBag::default();
+        bag.reconcile(library.iter());
+        let candidates = paths(&["b"]);
+        assert_eq!(bag.next(&library, &candidates), Some(PathBuf::from("b")));
+        // "b" is played, so a new round starts rather than repeating it right away.
+        assert_eq!(bag.next(&library, &candidates), Some(PathBuf::from("b")));
+        assert_eq!(bag.entries().filter(|(_, played)| *played).count(), 1);
+    }
+
+    #[test]
+    fn shuffle_bag_reconciles_library_changes() {
+        let mut bag = ShuffleBag::parse_legacy("1\ta\n0\tb\n1\tc\n0\td\nmalformed\n");
+        assert_eq!(
+            bag.entries()
+                .map(|(path, played)| (path.to_str().unwrap(), played))
+                .collect::<Vec<_>>(),
+            [("a", true), ("b", false), ("c", true), ("d", false)]
+        );
+
+        let library = paths(&["a", "b", "d", "e", "f"]);
+        bag.reconcile(library.iter());
+        let entries = bag
+            .entries()
+            .map(|(path, played)| (path.clone(), played))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sorted(entries.iter().map(|(path, _)| path.clone()).collect()),
+            library
+        );
+        for (path, played) in &entries {
+            assert_eq!(*played, path == Path::new("a"), "{:?}", path);
+        }
+        // Wallpapers kept their relative order.
+        let kept = entries
+            .iter()
+            .map(|(path, _)| path.to_str().unwrap())
+            .filter(|path| ["a", "b", "d"].contains(path))
+            .collect::<Vec<_>>();
+        assert_eq!(kept, ["a", "b", "d"]);
+
+        let mut rest = (0..4)
+            .map(|_| bag.next(&library, &library).unwrap())
+            .collect::<Vec<_>>();
+        rest.sort();
+        assert_eq!(rest, paths(&["b", "d", "e", "f"]));
+    }
+}
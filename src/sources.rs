@@ -0,0 +1,611 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
+
+use tracing::{debug, info, warn};
+
+use crate::backends::WallpaperBackend;
+use crate::config::Config;
+use crate::dynamic::{self, DynamicSet};
+use crate::images::{self, ImageFormat, ImageSize};
+
+/// Name of the gitignore-style file excluding paths from the scan of its directory.
+pub const IGNORE_FILE_NAME: &str = ".wallpaperignore";
+
+/// A folder of wallpapers, or the manifest of a dynamic set, picked with a probability proportional
+/// to its weight.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub path: PathBuf,
+    pub weight: f64,
+    /// Names the periods of the schedule can pick the source by.
+    pub tags: Vec<String>,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())
+    }
+}
+
+/// Parses `path` or `path=weight`, the weight defaulting to 1.
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (path, weight) = match value.rsplit_once('=') {
+            Some((path, weight)) => match weight.trim().parse::<f64>() {
+                Ok(weight) => (path, weight),
+                Err(_) => (value, 1.0),
+            },
+            None => (value, 1.0),
+        };
+
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(format!("Invalid weight {} for source {}.", weight, path));
+        }
+        Ok(Source {
+            path: PathBuf::from(shellexpand::tilde(path.trim()).to_string()),
+            weight,
+            tags: Vec::new(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScanOptions {
+    /// How many levels of subdirectories to descend into, 0 only scans the directory itself.
+    pub max_depth: u32,
+    pub follow_symlinks: bool,
+}
+
+/// Wallpapers found in a single source.
+#[derive(Debug)]
+pub struct SourceWallpapers {
+    pub source: Source,
+    pub wallpapers: Vec<PathBuf>,
+    /// Set when the source is a dynamic set, whose frames are the wallpapers.
+    pub dynamic_set: Option<DynamicSet>,
+}
+
+/// Wallpapers of every source, and their sizes when the size filter needs them.
+#[derive(Debug)]
+pub struct Libraries {
+    pub libraries: Vec<SourceWallpapers>,
+    pub wallpaper_sizes: HashMap<PathBuf, ImageSize>,
+    pub scanned_at: Instant,
+}
+
+/// Wallpapers whose size cannot be read are dropped when the size filter is active.
+#[tracing::instrument(skip(config))]
+pub fn scan_libraries(config: &Config, backend: &dyn WallpaperBackend) -> Libraries {
+    let size_filter = &config.size_filter;
+    let allowed_formats = get_allowed_formats(backend, &config.image_formats);
+    let mut sources = config.sources.clone();
+    for source in config
+        .schedule
+        .iter()
+        .flat_map(|schedule| schedule.sources())
+    {
+        if !sources.iter().any(|known| known.path == source.path) {
+            sources.push(source.clone());
+        }
+    }
+    let location = config
+        .schedule
+        .as_ref()
+        .and_then(|schedule| schedule.location);
+    let mut libraries = sources
+        .into_iter()
+        .map(|source| {
+            if !dynamic::is_dynamic_set(&source.path) {
+                return SourceWallpapers {
+                    wallpapers: find_wallpapers(
+                        &source.path,
+                        &config.scan_options,
+                        &allowed_formats,
+                    ),
+                    source,
+                    dynamic_set: None,
+                };
+            }
+            match DynamicSet::load(&source.path, location) {
+                Ok(dynamic_set) => SourceWallpapers {
+                    wallpapers: dynamic_set
+                        .frames
+                        .iter()
+                        .map(|frame| frame.path.clone())
+                        .filter(|file_path| is_image(file_path, &allowed_formats))
+                        .collect(),
+                    source,
+                    dynamic_set: Some(dynamic_set),
+                },
+                Err(err) => {
+                    warn!("Skipping dynamic set {}: {}", source.path.display(), err);
+                    SourceWallpapers {
+                        wallpapers: Vec::new(),
+                        source,
+                        dynamic_set: None,
+                    }
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    let wallpaper_sizes = if size_filter.is_active() {
+        read_wallpaper_sizes(libraries.iter().flat_map(|library| &library.wallpapers))
+    } else {
+        HashMap::new()
+    };
+    if size_filter.is_active() {
+        for library in &mut libraries {
+            library
+                .wallpapers
+                .retain(|file_path| wallpaper_sizes.contains_key(file_path));
+        }
+    }
+    Libraries {
+        libraries,
+        wallpaper_sizes,
+        scanned_at: Instant::now(),
+    }
+}
+
+#[tracing::instrument]
+pub fn find_wallpapers(
+    wallpaper_directory_path: &PathBuf,
+    scan_options: &ScanOptions,
+    allowed_formats: &[ImageFormat],
+) -> Vec<PathBuf> {
+    scan_directory(wallpaper_directory_path, scan_options)
+        .into_iter()
+        .filter(|file_path| is_image(file_path, allowed_formats))
+        .collect::<Vec<_>>()
+}
+
+/// Reads the size of every wallpaper, dropping the ones whose header cannot be parsed.
+#[tracing::instrument(skip(wallpapers))]
+pub fn read_wallpaper_sizes<'a>(
+    wallpapers: impl Iterator<Item = &'a PathBuf>,
+) -> HashMap<PathBuf, ImageSize> {
+    wallpapers
+        .filter_map(|file_path| match images::read_image_size(file_path) {
+            Ok(size) => Some((file_path.clone(), size)),
+            Err(err) => {
+                warn!("Skipping {}: {}", file_path.display(), err);
+                None
+            }
+        })
+        .collect()
+}
+
+#[tracing::instrument]
+pub fn is_image(path: &Path, allowed_formats: &[ImageFormat]) -> bool {
+    match images::detect_image_format(path) {
+        Ok(Some(format)) => allowed_formats.contains(&format),
+        Ok(None) => false,
+        Err(err) => {
+            warn!("Failed to read {}: {}", path.display(), err);
+            false
+        }
+    }
+}
+
+/// Requested formats that the backend can display.
+#[tracing::instrument]
+pub fn get_allowed_formats(
+    backend: &dyn WallpaperBackend,
+    requested_formats: &[ImageFormat],
+) -> Vec<ImageFormat> {
+    let (allowed_formats, unsupported_formats): (Vec<_>, Vec<_>) = requested_formats
+        .iter()
+        .copied()
+        .partition(|format| backend.supported_formats().contains(format));
+    if !unsupported_formats.is_empty() {
+        info!(
+            "Ignoring formats {} does not support: {:?}",
+            backend.name(),
+            unsupported_formats
+        );
+    }
+    allowed_formats
+}
+
+#[derive(Debug, PartialEq)]
+enum EntryKind {
+    Directory,
+    File,
+}
+
+#[derive(Debug)]
+struct IgnoreRule {
+    components: Vec<String>,
+    negated: bool,
+    directory_only: bool,
+    anchored: bool,
+}
+
+#[derive(Debug)]
+struct IgnoreFile {
+    directory: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+/// Lists the files below `directory`. Unreadable directories are logged and skipped so one bad
+/// folder does not hide the rest of the library.
+#[tracing::instrument]
+pub fn scan_directory(directory: &Path, options: &ScanOptions) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    scan(
+        directory,
+        0,
+        options,
+        &mut Vec::new(),
+        &mut HashSet::new(),
+        &mut files,
+    );
+    files
+}
+
+fn scan(
+    directory: &Path,
+    depth: u32,
+    options: &ScanOptions,
+    ignore_files: &mut Vec<IgnoreFile>,
+    visited_directories: &mut HashSet<(u64, u64)>,
+    files: &mut Vec<PathBuf>,
+) {
+    match fs::metadata(directory) {
+        Ok(metadata) => {
+            if !visited_directories.insert((metadata.dev(), metadata.ino())) {
+                warn!(
+                    "Skipping {}, already scanned through a symlink loop",
+                    directory.display()
+                );
+                return;
+            }
+        }
+        Err(err) => {
+            warn!("Failed to open {}: {}", directory.display(), err);
+            return;
+        }
+    }
+
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Failed to open {}: {}", directory.display(), err);
+            return;
+        }
+    };
+
+    let has_ignore_file = match read_ignore_file(directory) {
+        Some(ignore_file) => {
+            ignore_files.push(ignore_file);
+            true
+        }
+        None => false,
+    };
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("Failed to read entry in {}: {}", directory.display(), err);
+                continue;
+            }
+        };
+        let path = entry.path();
+
+        let Some(kind) = get_entry_kind(&entry, &path, options.follow_symlinks) else {
+            continue;
+        };
+        if is_ignored(&path, &kind, ignore_files) {
+            debug!("Ignoring {}", path.display());
+            continue;
+        }
+
+        if kind == EntryKind::Directory {
+            if depth < options.max_depth {
+                scan(
+                    &path,
+                    depth + 1,
+                    options,
+                    ignore_files,
+                    visited_directories,
+                    files,
+                );
+            }
+        } else {
+            files.push(path);
+        }
+    }
+
+    if has_ignore_file {
+        ignore_files.pop();
+    }
+}
+
+/// Resolves symlinks only when they are followed. Anything that is neither a directory nor a
+/// regular file is skipped.
+fn get_entry_kind(entry: &fs::DirEntry, path: &Path, follow_symlinks: bool) -> Option<EntryKind> {
+    let file_type = entry.file_type().ok()?;
+    if !file_type.is_symlink() {
+        return if file_type.is_dir() {
+            Some(EntryKind::Directory)
+        } else if file_type.is_file() {
+            Some(EntryKind::File)
+        } else {
+            None
+        };
+    }
+
+    if !follow_symlinks {
+        debug!("Not following symlink {}", path.display());
+        return None;
+    }
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Some(EntryKind::Directory),
+        Ok(metadata) if metadata.is_file() => Some(EntryKind::File),
+        Ok(_) => None,
+        Err(err) => {
+            warn!("Skipping broken symlink {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+#[tracing::instrument]
+fn read_ignore_file(directory: &Path) -> Option<IgnoreFile> {
+    let ignore_file_path = directory.join(IGNORE_FILE_NAME);
+    let content = match fs::read_to_string(&ignore_file_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            warn!("Failed to read {}: {}", ignore_file_path.display(), err);
+            return None;
+        }
+    };
+
+    info!("Using {}", ignore_file_path.display());
+    Some(IgnoreFile {
+        directory: directory.to_path_buf(),
+        rules: content.lines().filter_map(parse_ignore_rule).collect(),
+    })
+}
+
+/// Parses a gitignore line: `#` starts a comment, `!` re-includes, a trailing `/` only matches
+/// directories and any other `/` anchors the pattern to the ignore file's directory.
+fn parse_ignore_rule(line: &str) -> Option<IgnoreRule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(pattern) => (true, pattern),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (directory_only, pattern) = match pattern.strip_suffix('/') {
+        Some(pattern) => (true, pattern),
+        None => (false, pattern),
+    };
+    let anchored = pattern.contains('/');
+    let components = pattern
+        .trim_start_matches('/')
+        .split('/')
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if components.iter().all(String::is_empty) {
+        return None;
+    }
+
+    Some(IgnoreRule {
+        components,
+        negated,
+        directory_only,
+        anchored,
+    })
+}
+
+/// Applies the ignore files from the outermost directory inwards, the last matching rule wins.
+fn is_ignored(path: &Path, kind: &EntryKind, ignore_files: &[IgnoreFile]) -> bool {
+    let mut ignored = false;
+    for ignore_file in ignore_files {
+        let Ok(relative_path) = path.strip_prefix(&ignore_file.directory) else {
+            continue;
+        };
+        let names = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+        let components = names.iter().map(|name| name.as_ref()).collect::<Vec<_>>();
+
+        for rule in &ignore_file.rules {
+            if rule.directory_only && *kind != EntryKind::Directory {
+                continue;
+            }
+            let matched = if rule.anchored {
+                matches_components(&rule.components, &components)
+            } else {
+                components
+                    .last()
+                    .is_some_and(|name| matches_glob(&rule.components[0], name))
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+    }
+    ignored
+}
+
+fn matches_components(pattern: &[String], components: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => components.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=components.len()).any(|skipped| matches_components(rest, &components[skipped..]))
+        }
+        Some((first, rest)) => match components.split_first() {
+            Some((component, remaining)) => {
+                matches_glob(first, component) && matches_components(rest, remaining)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Matches a single path component against `*`, `?` and `[...]` wildcards.
+fn matches_glob(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    matches_glob_chars(&pattern, &name)
+}
+
+fn matches_glob_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => {
+            (0..=name.len()).any(|skipped| matches_glob_chars(&pattern[1..], &name[skipped..]))
+        }
+        Some('?') => !name.is_empty() && matches_glob_chars(&pattern[1..], &name[1..]),
+        Some('[') => match (pattern.iter().position(|c| *c == ']'), name.first()) {
+            (Some(end), Some(char)) if end > 1 => {
+                matches_class(&pattern[1..end], *char)
+                    && matches_glob_chars(&pattern[end + 1..], &name[1..])
+            }
+            _ => false,
+        },
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && matches_glob_chars(&pattern[2..], &name[1..])
+        }
+        Some(char) => name.first() == Some(char) && matches_glob_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Matches a bracket expression body such as `a-z0-9` or `!.`.
+fn matches_class(class: &[char], char: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    let mut index = 0;
+    while index < class.len() {
+        if index + 2 < class.len() && class[index + 1] == '-' {
+            matched |= (class[index]..=class[index + 2]).contains(&char);
+            index += 3;
+        } else {
+            matched |= class[index] == char;
+            index += 1;
+        }
+    }
+    matched != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore_file(lines: &str) -> IgnoreFile {
+        IgnoreFile {
+            directory: PathBuf::from("/wallpapers"),
+            rules: lines.lines().filter_map(parse_ignore_rule).collect(),
+        }
+    }
+
+    fn ignores(lines: &str, path: &str, kind: EntryKind) -> bool {
+        is_ignored(
+            &Path::new("/wallpapers").join(path),
+            &kind,
+            &[ignore_file(lines)],
+        )
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        assert!(ignore_file("# comment\n\n   \n/\n").rules.is_empty());
+        assert!(ignores("\\#hash.png", "#hash.png", EntryKind::File));
+    }
+
+    #[test]
+    fn negation_reincludes_matches() {
+        let lines = "*.png\n!keep.png";
+        assert!(ignores(lines, "drop.png", EntryKind::File));
+        assert!(!ignores(lines, "keep.png", EntryKind::File));
+        assert!(!ignores(lines, "nested/keep.png", EntryKind::File));
+        // The last matching rule wins.
+        assert!(ignores("!keep.png\n*.png", "keep.png", EntryKind::File));
+    }
+
+    #[test]
+    fn slashes_anchor_patterns() {
+        assert!(ignores("/top.png", "top.png", EntryKind::File));
+        assert!(!ignores("/top.png", "nested/top.png", EntryKind::File));
+        assert!(ignores("nested/*.png", "nested/a.png", EntryKind::File));
+        assert!(!ignores(
+            "nested/*.png",
+            "other/nested/a.png",
+            EntryKind::File
+        ));
+        // Without a slash the pattern matches the name at any depth.
+        assert!(ignores("top.png", "deep/down/top.png", EntryKind::File));
+    }
+
+    #[test]
+    fn double_stars_match_any_depth() {
+        assert!(ignores("**/drafts", "drafts", EntryKind::Directory));
+        assert!(ignores("**/drafts", "a/b/drafts", EntryKind::Directory));
+        assert!(ignores("a/**/b.png", "a/b.png", EntryKind::File));
+        assert!(ignores("a/**/b.png", "a/x/y/b.png", EntryKind::File));
+        assert!(!ignores("a/**/b.png", "x/a/b.png", EntryKind::File));
+        assert!(ignores("old/**", "old/2019/a.png", EntryKind::File));
+    }
+
+    #[test]
+    fn trailing_slashes_only_match_directories() {
+        assert!(ignores("drafts/", "drafts", EntryKind::Directory));
+        assert!(ignores("drafts/", "nested/drafts", EntryKind::Directory));
+        assert!(!ignores("drafts/", "drafts", EntryKind::File));
+        assert!(ignores(
+            "/nested/drafts/",
+            "nested/drafts",
+            EntryKind::Directory
+        ));
+        assert!(!ignores("/nested/drafts/", "drafts", EntryKind::Directory));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches_glob("img_??.jp*g", "img_01.jpeg"));
+        assert!(!matches_glob("img_??.jpg", "img_1.jpg"));
+        assert!(matches_glob("[a-c]*.png", "beach.png"));
+        assert!(!matches_glob("[!a-c]*.png", "beach.png"));
+        assert!(matches_glob("\\*.png", "*.png"));
+        assert!(!matches_glob("\\*.png", "a.png"));
+    }
+
+    #[test]
+    fn inner_ignore_files_apply_last() {
+        let outer = ignore_file("*.png");
+        let inner = IgnoreFile {
+            directory: PathBuf::from("/wallpapers/keep"),
+            rules: vec![parse_ignore_rule("!*.png").unwrap()],
+        };
+        let ignore_files = [outer, inner];
+        assert!(is_ignored(
+            Path::new("/wallpapers/a.png"),
+            &EntryKind::File,
+            &ignore_files
+        ));
+        assert!(!is_ignored(
+            Path::new("/wallpapers/keep/a.png"),
+            &EntryKind::File,
+            &ignore_files
+        ));
+    }
+}
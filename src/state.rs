@@ -0,0 +1,370 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use toml_edit::{value, ArrayOfTables, Document, Item, Table};
+use tracing::{info, warn};
+
+use crate::history::{History, HistoryEntry, ALL_OUTPUTS};
+use crate::marks::{Mark, MarkSet, Rating};
+use crate::selection::{SelectionMode, ShuffleBag};
+
+/// Version 2 keeps the size and hash of the favourites and banned wallpapers.
+pub const STATE_VERSION: i64 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrentWallpaper {
+    pub path: PathBuf,
+    pub applied_at: Option<i64>,
+}
+
+/// Everything remembered between runs.
+#[derive(Debug, Default)]
+pub struct State {
+    /// Wallpaper shown on each output, or on every output under `ALL_OUTPUTS`.
+    pub current: BTreeMap<String, CurrentWallpaper>,
+    pub history: History,
+    pub selection_mode: Option<SelectionMode>,
+    pub shuffle_bag: ShuffleBag,
+    pub favorites: MarkSet,
+    /// Wallpapers never picked again.
+    pub banned: MarkSet,
+    pub ratings: MarkSet<Rating>,
+    pub updated_at: Option<i64>,
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Offset of the local time zone from UTC at `timestamp`, in seconds.
+pub fn local_utc_offset(timestamp: i64) -> i64 {
+    let time = timestamp as libc::time_t;
+    // SAFETY: `tm` is plain data and both pointers are valid for the duration of the call.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff
+}
+
+/// Seconds since local midnight at `timestamp`.
+pub fn local_seconds_of_day(timestamp: i64) -> u32 {
+    (timestamp + local_utc_offset(timestamp)).rem_euclid(86400) as u32
+}
+
+/// `$XDG_STATE_HOME/random-wallpaper/state.toml`.
+#[tracing::instrument]
+pub fn get_default_state_file_path() -> PathBuf {
+    dirs::state_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("state")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("random-wallpaper")
+        .join("state.toml")
+}
+
+impl State {
+    /// Reads the state file. Without one, the cache and shuffle files of older versions are
+    /// migrated when present. A corrupt state file is moved aside so the next save starts over.
+    #[tracing::instrument]
+    pub fn load(state_file_path: &Path, legacy_cache_file_path: &Path) -> State {
+        match fs::read_to_string(state_file_path) {
+            Ok(content) => match State::parse(&content) {
+                Ok(state) => state,
+                Err(err) => {
+                    let mut corrupt_file_path = state_file_path.as_os_str().to_owned();
+                    corrupt_file_path.push(".corrupt");
+                    warn!(
+                        "Ignoring invalid state file {}, moved to {:?}: {}",
+                        state_file_path.display(),
+                        corrupt_file_path,
+                        err
+                    );
+                    if let Err(err) = fs::rename(state_file_path, &corrupt_file_path) {
+                        warn!("Failed to move {}: {}", state_file_path.display(), err);
+                    }
+                    State::default()
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                State::migrate_legacy(legacy_cache_file_path)
+            }
+            Err(err) => {
+                warn!("Failed to read {}: {}", state_file_path.display(), err);
+                State::default()
+            }
+        }
+    }
+
+    #[tracing::instrument]
+    fn migrate_legacy(legacy_cache_file_path: &Path) -> State {
+        let Ok(cache) = fs::read_to_string(legacy_cache_file_path) else {
+            return State::default();
+        };
+
+        let mut state = State {
+            history: History::parse_legacy(&cache),
+            ..State::default()
+        };
+        for entry in &state.history.entries {
+            if entry.output == ALL_OUTPUTS {
+                state.current.clear();
+            }
+            state.current.insert(
+                entry.output.clone(),
+                CurrentWallpaper {
+                    path: entry.path.clone(),
+                    applied_at: None,
+                },
+            );
+        }
+
+        let mut shuffle_file_path = legacy_cache_file_path.as_os_str().to_owned();
+        shuffle_file_path.push(".shuffle");
+        if let Ok(shuffle) = fs::read_to_string(&shuffle_file_path) {
+            state.shuffle_bag = ShuffleBag::parse_legacy(&shuffle);
+            state.selection_mode = Some(SelectionMode::Shuffle);
+        }
+
+        info!(
+            "Migrated {} wallpapers from {}",
+            state.history.entries.len(),
+            legacy_cache_file_path.display()
+        );
+        state
+    }
+
+    fn parse(content: &str) -> Result<State, String> {
+        let document = content.parse::<Document>().map_err(|err| err.to_string())?;
+
+        let version = document
+            .get("version")
+            .and_then(Item::as_integer)
+            .ok_or("missing version")?;
+        if version > STATE_VERSION {
+            return Err(format!(
+                "version {} is newer than the supported version {}",
+                version, STATE_VERSION
+            ));
+        }
+
+        let mut state = State {
+            updated_at: document.get("updated_at").and_then(Item::as_integer),
+            ..State::default()
+        };
+
+        state.favorites = parse_marks(document.get("favorites"));
+        state.banned = parse_marks(document.get("banned"));
+        state.ratings = parse_mark_tables(document.get("ratings"), |table| {
+            table
+                .get("rating")
+                .and_then(Item::as_integer)
+                .and_then(|rating| u8::try_from(rating).ok())
+                .and_then(Rating::new)
+        });
+
+        if let Some(current) = document.get("current").and_then(Item::as_table) {
+            for (output, wallpaper) in current.iter() {
+                let Some(path) = wallpaper.get("path").and_then(Item::as_str) else {
+                    continue;
+                };
+                state.current.insert(
+                    output.to_string(),
+                    CurrentWallpaper {
+                        path: PathBuf::from(path),
+                        applied_at: wallpaper.get("applied_at").and_then(Item::as_integer),
+                    },
+                );
+            }
+        }
+
+        if let Some(history) = document.get("history").and_then(Item::as_array_of_tables) {
+            state.history.entries = history
+                .iter()
+                .filter_map(|entry| {
+                    Some(HistoryEntry {
+                        output: entry.get("output")?.as_str()?.to_string(),
+                        path: PathBuf::from(entry.get("path")?.as_str()?),
+                        applied_at: entry.get("applied_at").and_then(Item::as_integer),
+                    })
+                })
+                .collect();
+        }
+
+        if let Some(selection) = document.get("selection").and_then(Item::as_table) {
+            state.selection_mode = selection
+                .get("mode")
+                .and_then(Item::as_str)
+                .and_then(|mode| mode.parse().ok());
+            if let Some(shuffle) = selection.get("shuffle").and_then(Item::as_array_of_tables) {
+                state.shuffle_bag = ShuffleBag::from_entries(shuffle.iter().filter_map(|entry| {
+                    Some((
+                        PathBuf::from(entry.get("path")?.as_str()?),
+                        entry.get("played")?.as_bool()?,
+                    ))
+                }));
+            }
+        }
+
+        Ok(state)
+    }
+
+    fn to_document(&self) -> Document {
+        let mut document = Document::new();
+        document["version"] = value(STATE_VERSION);
+        if let Some(updated_at) = self.updated_at {
+            document["updated_at"] = value(updated_at);
+        }
+
+        if !self.favorites.is_empty() {
+            document["favorites"] = to_array_of_tables(&self.favorites, |_, _| {});
+        }
+        if !self.banned.is_empty() {
+            document["banned"] = to_array_of_tables(&self.banned, |_, _| {});
+        }
+        if !self.ratings.is_empty() {
+            document["ratings"] = to_array_of_tables(&self.ratings, |rating, table| {
+                table["rating"] = value(i64::from(rating.stars()));
+            });
+        }
+
+        let mut current = Table::new();
+        current.set_implicit(true);
+        for (output, wallpaper) in &self.current {
+            let mut table = Table::new();
+            table["path"] = value(wallpaper.path.to_string_lossy().as_ref());
+            if let Some(applied_at) = wallpaper.applied_at {
+                table["applied_at"] = value(applied_at);
+            }
+            current[output.as_str()] = Item::Table(table);
+        }
+        document["current"] = Item::Table(current);
+
+        let mut selection = Table::new();
+        if let Some(selection_mode) = self.selection_mode {
+            selection["mode"] = value(selection_mode.to_string());
+        }
+        let mut shuffle = ArrayOfTables::new();
+        for (path, played) in self.shuffle_bag.entries() {
+            let mut table = Table::new();
+            table["path"] = value(path.to_string_lossy().as_ref());
+            table["played"] = value(played);
+            shuffle.push(table);
+        }
+        if !shuffle.is_empty() {
+            selection["shuffle"] = Item::ArrayOfTables(shuffle);
+        }
+        document["selection"] = Item::Table(selection);
+
+        let mut history = ArrayOfTables::new();
+        for entry in &self.history.entries {
+            let mut table = Table::new();
+            table["output"] = value(entry.output.as_str());
+            table["path"] = value(entry.path.to_string_lossy().as_ref());
+            if let Some(applied_at) = entry.applied_at {
+                table["applied_at"] = value(applied_at);
+            }
+            history.push(table);
+        }
+        document["history"] = Item::ArrayOfTables(history);
+
+        document
+    }
+
+    /// Writes the state to a temporary file first and renames it over the old one, so a crash
+    /// never leaves a truncated state behind.
+    #[tracing::instrument(skip(self))]
+    pub fn save(&self, state_file_path: &Path) -> io::Result<()> {
+        if let Some(state_directory) = state_file_path.parent() {
+            fs::create_dir_all(state_directory)?;
+        }
+
+        let mut temporary_file_path = state_file_path.as_os_str().to_owned();
+        temporary_file_path.push(".tmp");
+        let mut temporary_file = File::create(&temporary_file_path)?;
+        temporary_file.write_all(self.to_document().to_string().as_bytes())?;
+        temporary_file.sync_all()?;
+        fs::rename(&temporary_file_path, state_file_path)
+    }
+
+    /// Remembers `path` as the wallpaper now shown on `output`.
+    pub fn record(&mut self, output: &str, path: PathBuf) {
+        let applied_at = now();
+        if output == ALL_OUTPUTS {
+            self.current.clear();
+        }
+        self.current.insert(
+            output.to_string(),
+            CurrentWallpaper {
+                path: path.clone(),
+                applied_at: Some(applied_at),
+            },
+        );
+        self.history.push(output, path, applied_at);
+        self.updated_at = Some(applied_at);
+    }
+}
+
+/// Reads `[[favorites]]` tables, or the plain path arrays of version 1 which get their hashes
+/// on the next scan.
+fn parse_marks(item: Option<&Item>) -> MarkSet {
+    match item {
+        Some(Item::ArrayOfTables(_)) => parse_mark_tables(item, |_| Some(())),
+        Some(item) => MarkSet::from_marks(
+            item.as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|path| path.as_str())
+                .map(|path| Mark {
+                    path: PathBuf::from(path),
+                    size: None,
+                    sha1: None,
+                    value: (),
+                }),
+        ),
+        None => MarkSet::default(),
+    }
+}
+
+/// Reads tables with a `path`, `size` and `sha1`, skipping the ones `parse_value` rejects.
+fn parse_mark_tables<T>(
+    item: Option<&Item>,
+    parse_value: impl Fn(&Table) -> Option<T>,
+) -> MarkSet<T> {
+    let Some(tables) = item.and_then(Item::as_array_of_tables) else {
+        return MarkSet::default();
+    };
+    MarkSet::from_marks(tables.iter().filter_map(|table| {
+        Some(Mark {
+            path: PathBuf::from(table.get("path")?.as_str()?),
+            size: table
+                .get("size")
+                .and_then(Item::as_integer)
+                .and_then(|size| u64::try_from(size).ok()),
+            sha1: table.get("sha1").and_then(Item::as_str).map(str::to_string),
+            value: parse_value(table)?,
+        })
+    }))
+}
+
+fn to_array_of_tables<T>(marks: &MarkSet<T>, write_value: impl Fn(&T, &mut Table)) -> Item {
+    let mut tables = ArrayOfTables::new();
+    for mark in marks.iter() {
+        let mut table = Table::new();
+        table["path"] = value(mark.path.to_string_lossy().as_ref());
+        if let Some(size) = mark.size {
+            table["size"] = value(size as i64);
+        }
+        if let Some(sha1) = &mark.sha1 {
+            table["sha1"] = value(sha1.as_str());
+        }
+        write_value(&mark.value, &mut table);
+        tables.push(table);
+    }
+    Item::ArrayOfTables(tables)
+}
@@ -0,0 +1,244 @@
+//! Templates with `{name}` placeholders, used for the command of the custom backend and the text
+//! of notifications.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum TemplateError {
+    Empty,
+    UnclosedQuote(char),
+    UnclosedPlaceholder(usize),
+    UnmatchedBrace(usize),
+    UnknownPlaceholder(String, &'static [&'static str]),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Empty => write!(f, "template is empty"),
+            TemplateError::UnclosedQuote(quote) => write!(f, "missing closing {}", quote),
+            TemplateError::UnclosedPlaceholder(position) => {
+                write!(f, "placeholder opened at {} is never closed", position)
+            }
+            TemplateError::UnmatchedBrace(position) => write!(
+                f,
+                "unmatched '}}' at {}, use '}}}}' for a literal brace",
+                position
+            ),
+            TemplateError::UnknownPlaceholder(name, placeholders) => write!(
+                f,
+                "unknown placeholder {{{}}}, expected one of {}",
+                name,
+                placeholders.join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// Text with `{name}` placeholders, `{{`/`}}` being literal braces.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /// Parses `template` as a single text, quotes and whitespace included.
+    pub fn parse(
+        template: &str,
+        placeholders: &'static [&'static str],
+    ) -> Result<Template, TemplateError> {
+        Ok(parse(template, placeholders, false)?
+            .pop()
+            .unwrap_or_default())
+    }
+
+    /// Splits `template` into arguments on unquoted whitespace, single and double quotes
+    /// grouping words.
+    pub fn parse_arguments(
+        template: &str,
+        placeholders: &'static [&'static str],
+    ) -> Result<Vec<Template>, TemplateError> {
+        parse(template, placeholders, true)
+    }
+
+    /// Whether `{placeholder}` appears in the template.
+    pub fn uses(&self, placeholder: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Placeholder(name) if name == placeholder))
+    }
+
+    /// The text of a template without placeholders.
+    pub fn literal(&self) -> Option<String> {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => Some(literal.as_str()),
+                Segment::Placeholder(_) => None,
+            })
+            .collect()
+    }
+
+    /// Fills each placeholder with `value(name)`.
+    pub fn render(&self, mut value: impl FnMut(&str) -> String) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.clone(),
+                Segment::Placeholder(name) => value(name),
+            })
+            .collect()
+    }
+}
+
+#[tracing::instrument]
+fn parse(
+    template: &str,
+    placeholders: &'static [&'static str],
+    split_arguments: bool,
+) -> Result<Vec<Template>, TemplateError> {
+    let mut templates = Vec::new();
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut in_argument = !split_arguments;
+    let mut quote = None;
+    let mut chars = template.char_indices().peekable();
+
+    while let Some((position, char)) = chars.next() {
+        match char {
+            '\'' | '"' if split_arguments && quote.is_none() => {
+                quote = Some(char);
+                in_argument = true;
+            }
+            _ if quote == Some(char) => quote = None,
+            '{' | '}' if chars.peek().map(|(_, next)| *next) == Some(char) => {
+                chars.next();
+                literal.push(char);
+                in_argument = true;
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '}')) => break,
+                        Some((_, next)) => name.push(next),
+                        None => return Err(TemplateError::UnclosedPlaceholder(position)),
+                    }
+                }
+                if !placeholders.contains(&name.as_str()) {
+                    return Err(TemplateError::UnknownPlaceholder(name, placeholders));
+                }
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Placeholder(name));
+                in_argument = true;
+            }
+            '}' => return Err(TemplateError::UnmatchedBrace(position)),
+            _ if split_arguments && char.is_whitespace() && quote.is_none() => {
+                if in_argument {
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    templates.push(Template {
+                        segments: std::mem::take(&mut segments),
+                    });
+                    in_argument = false;
+                }
+            }
+            _ => {
+                literal.push(char);
+                in_argument = true;
+            }
+        }
+    }
+
+    if let Some(quote) = quote {
+        return Err(TemplateError::UnclosedQuote(quote));
+    }
+    if in_argument {
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        templates.push(Template { segments });
+    }
+    Ok(templates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLACEHOLDERS: [&str; 2] = ["path", "output"];
+
+    fn render(template: &Template) -> String {
+        template.render(|name| format!("<{}>", name))
+    }
+
+    #[test]
+    fn keeps_quotes_and_whitespace_in_text() {
+        let template = Template::parse(" '{path}'  on \"{output}\" ", &PLACEHOLDERS).unwrap();
+        assert_eq!(render(&template), " '<path>'  on \"<output>\" ");
+        assert!(template.uses("output"));
+        assert!(!template.uses("path ") && !template.uses("fit"));
+        assert_eq!(template.literal(), None);
+
+        let template = Template::parse("{{literal}} }}", &PLACEHOLDERS).unwrap();
+        assert_eq!(template.literal().as_deref(), Some("{literal} }"));
+        assert_eq!(
+            Template::parse("", &PLACEHOLDERS).unwrap(),
+            Template::default()
+        );
+    }
+
+    #[test]
+    fn splits_arguments() {
+        let arguments = Template::parse_arguments(
+            r#"setter --on={output} "{path}" 'a b'"" '' "#,
+            &PLACEHOLDERS,
+        )
+        .unwrap();
+        assert_eq!(
+            arguments.iter().map(render).collect::<Vec<_>>(),
+            ["setter", "--on=<output>", "<path>", "a b", ""]
+        );
+        assert!(Template::parse_arguments(" \t", &PLACEHOLDERS)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn reports_invalid_templates() {
+        for (template, error) in [
+            ("{path", "placeholder opened at 0 is never closed"),
+            (
+                "a {path}}",
+                "unmatched '}' at 8, use '}}' for a literal brace",
+            ),
+            (
+                "{file}",
+                "unknown placeholder {file}, expected one of path, output",
+            ),
+            ("{}", "unknown placeholder {}, expected one of path, output"),
+        ] {
+            for result in [
+                Template::parse(template, &PLACEHOLDERS).map(|_| ()),
+                Template::parse_arguments(template, &PLACEHOLDERS).map(|_| ()),
+            ] {
+                assert_eq!(result.unwrap_err().to_string(), error);
+            }
+        }
+
+        assert!(Template::parse("it's", &PLACEHOLDERS).is_ok());
+        assert!(matches!(
+            Template::parse_arguments("it's", &PLACEHOLDERS),
+            Err(TemplateError::UnclosedQuote('\''))
+        ));
+    }
+}